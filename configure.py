@@ -10,6 +10,7 @@ import sys
 
 ROOT_DIR = os.path.dirname(os.path.realpath(__file__))
 SRC_DIR = os.path.join(ROOT_DIR, 'src')
+TOOLS_DIR = os.path.join(ROOT_DIR, 'tools')
 BUILD_DIR = os.path.join(ROOT_DIR, 'build')
 
 SUPPORTED_ARCHS = [ 'x86_64', 'aarch64' ];
@@ -30,6 +31,13 @@ parser.add_argument('--level', dest='level', action='store',
 parser.add_argument('--prefix', dest='prefix', action='store',
                     help='binutils prefix (default: <arch>%s)'% \
                         DEFAULT_INFIX)
+parser.add_argument('--debug-heap', dest='debug_heap', action='store_true',
+                    help='check kernel heap for corruption and leaks')
+parser.add_argument('--cmdline', dest='cmdline', action='store', default='',
+                    help='kernel command line passed by QEMU on \'make run\'')
+parser.add_argument('--dtb', dest='dtb', action='store',
+                    help='device tree blob passed by QEMU on \'make run\' ' + \
+                        '(aarch64 only)')
 args = parser.parse_args()
 
 if not args.prefix:
@@ -64,6 +72,7 @@ objcopy = find_program(args.prefix+'objcopy')
 ar = find_program(args.prefix+'ar')
 gas = find_program(args.prefix+'as')
 ld = find_program(args.prefix+'ld')
+nm = find_program(args.prefix+'nm')
 qemu = find_program('qemu-system-'+args.arch)
 
 def read_build_json(filename):
@@ -96,11 +105,11 @@ def render(makefile, str):
     makefile.write(str.replace(ROOT_DIR, '$(ROOT)'))
 
 class Module:
-    def __init__(self, name):
+    def __init__(self, name, dependencies):
         self.name = name
         self.asm_files = []
         self.rust_files =[]
-        self.dependencies = []
+        self.dependencies = [rlib_target(m) for m in dependencies]
         self.build_dirs = []
         self.rust_args = []
         self.scan_dirs(self.path())
@@ -161,9 +170,15 @@ class Module:
             '--cfg arch_%s --sysroot /dev/null') % \
             (args.arch, args.level, args.arch)
 
-        # TODO: remove this in future (see issue #1)
+        if args.debug_heap:
+            flags += ' --cfg debug_heap'
+
+        # backtraces walk the chain of frame pointers
+        flags += ' -C llvm-args=-disable-fp-elim'
+
+        # the kernel is linked in the top 2GiB of the address space
         if args.arch == 'x86_64':
-            flags += ' -C relocation-model=static'
+            flags += ' -C code-model=kernel'
 
         lib_path = ' -L ' + ' -L '.join(
             map(lambda d: os.path.dirname(d), self.dependencies)) \
@@ -213,7 +228,9 @@ class Module:
             (self.name, self.name, target))
 
 data = read_build_json(os.path.join(SRC_DIR, 'build.json'))
-kmodules = map(lambda n: Module(n), data['kernelModules'])
+# third-party modules have no build.json, so their dependencies are here
+external = data.get('externalDependencies', {})
+kmodules = [Module(n, external.get(n, [])) for n in data['kernelModules']]
 kernel_target = os.path.join(build_dir(SRC_DIR), 'kernel', 'arwen.ker')
 
 def render_prolog(makefile):
@@ -228,19 +245,46 @@ def render_prolog(makefile):
 def render_kernel(makefile):
     source = ' '.join(map(lambda m: mod_target(m.name), kmodules))
     lds = os.path.join(SRC_DIR, 'kernel', 'arch-'+args.arch, 'kernel.lds')
+    kernel_dir = os.path.join(build_dir(SRC_DIR), 'kernel')
+    symbols_tool = os.path.join(TOOLS_DIR, 'symbols.py')
+    link = '\t@%s -nostdlib -z max-page-size=4096 -T %s %s %s -o %s\n'
 
     render(makefile, '\n# Kernel\n')
-    render(makefile, '\n%s: %s\n'%(kernel_target, source))
+    render(makefile, '\n%s: %s %s\n'%(kernel_target, source, symbols_tool))
     render(makefile, '\t@echo Creating \'%s\'\n'% \
         prettify_target(kernel_target))
-    render(makefile, '\t@%s -nostdlib -z max-page-size=4096 -T %s %s -o %s\n' \
-        %(ld, lds, source, kernel_target))
+
+    # the first pass links an empty symbol table, the second one links
+    # the table made of symbols the first one ends up with
+    first_elf = os.path.join(kernel_dir, 'arwen.0.elf')
+    elf = os.path.join(kernel_dir, 'arwen.elf')
+    empty = os.path.join(kernel_dir, 'symbols.0')
+    table = os.path.join(kernel_dir, 'symbols')
+    render(makefile, '\t@%s %s --empty > %s.s\n'% \
+        (sys.executable, symbols_tool, empty))
+    render(makefile, '\t@%s %s.s -o %s.o\n'%(gas, empty, empty))
+    render(makefile, link%(ld, lds, source, empty + '.o', first_elf))
+    render(makefile, '\t@%s -n %s | %s %s > %s.s\n'% \
+        (nm, first_elf, sys.executable, symbols_tool, table))
+    render(makefile, '\t@%s %s.s -o %s.o\n'%(gas, table, table))
+    render(makefile, link%(ld, lds, source, table + '.o', elf))
+
+    # multiboot loads a flat image using the a.out kludge header fields
+    if args.arch == 'x86_64':
+        render(makefile, '\t@%s -O binary %s %s\n'% \
+            (objcopy, elf, kernel_target))
+    else:
+        render(makefile, '\t@cp %s %s\n'%(elf, kernel_target))
     render(makefile, '\n.PHONY: arwen.ker\narwen.ker: %s\n'%kernel_target)
 
 def render_run(makefile):
     flags = ' -nographic'
     if args.arch == 'aarch64':
         flags += ' -machine type=virt -cpu cortex-a57'
+    if args.cmdline:
+        flags += ' -append \'%s\''%args.cmdline
+    if args.dtb and args.arch == 'aarch64':
+        flags += ' -dtb %s'%os.path.abspath(args.dtb)
     render(makefile, '\n# Run\n')
     render(makefile, '\n.PHONY: run\nrun: %s\n'%kernel_target)
     render(makefile, '\t@echo "Running QEMU (to exit press Ctrl-a x)"\n')
@@ -1,6 +1,12 @@
+use arch::cpu;
 use arch::device_tree;
+use arch::exception;
 use arch::memory;
+use arch::paging;
 use klog;
+use panic_log;
+use stack;
+use trace;
 
 const SERIAL_PORT_ADDRESS: usize = 0x0900_0000;
 const DEVICE_TREE_ADDRESS: usize = 0x4000_0000;
@@ -15,9 +21,31 @@ fn write(s: &str) {
     }
 }
 
+extern {
+    static __boot_stack_guard: u8;
+    static __boot_stack_top: u8;
+    static __emergency_stack_guard: u8;
+    static __emergency_stack_top: u8;
+}
+
 #[no_mangle]
 pub unsafe extern fn __boot() {
-    klog::init(write, klog::Level::Debug);
+    cpu::init_id();
+    klog::register(write, klog::Level::Debug);
+    exception::init();
     device_tree::init(DEVICE_TREE_ADDRESS);
-    memory::init();
+    klog::configure(device_tree::command_line());
+    trace::configure(device_tree::command_line());
+    panic_log::init();
+    if let Err(err) = memory::init() {
+        klog_fatal!("failed to detect memory: {:?}", err);
+        cpu::halt();
+    }
+    paging::init();
+    stack::register("boot", &__boot_stack_guard as *const u8 as usize,
+                    &__boot_stack_top as *const u8 as usize);
+    stack::register("emergency",
+                    &__emergency_stack_guard as *const u8 as usize,
+                    &__emergency_stack_top as *const u8 as usize);
+    trace::init(write);
 }
@@ -0,0 +1,117 @@
+#[inline(always)]
+pub fn frame_address() -> usize {
+    let x29: usize;
+    unsafe { asm!("mov $0, x29" : "=r"(x29)); }
+    x29
+}
+
+// Return address of the function this gets inlined into, used to record
+// allocation sites by the debug heap.
+#[cfg(debug_heap)]
+#[inline(always)]
+pub fn return_address() -> usize {
+    unsafe { *((frame_address() + 8) as *const usize) }
+}
+
+fn dcache_line_size() -> usize {
+    let ctr: usize;
+    unsafe { asm!("mrs $0, ctr_el0" : "=r"(ctr)); }
+    4 << ((ctr >> 16) & 0xF)
+}
+
+fn for_each_dcache_line(address: usize, size: usize, op: fn(usize)) {
+    let line = dcache_line_size();
+    let mut cur = address & !(line - 1);
+    while cur < address + size {
+        op(cur);
+        cur += line;
+    }
+    unsafe { asm!("dsb sy" : : : "memory" : "volatile"); }
+}
+
+// Device memory accesses bypass caches.
+pub const DMA_COHERENT: bool = false;
+
+// Writes dirty cache lines back to memory.
+pub fn clean_dcache(address: usize, size: usize) {
+    fn op(line: usize) {
+        unsafe { asm!("dc cvac, $0" : : "r"(line) : "memory" : "volatile"); }
+    }
+    for_each_dcache_line(address, size, op);
+}
+
+// Discards cache lines so that memory gets read anew.
+pub fn invalidate_dcache(address: usize, size: usize) {
+    fn op(line: usize) {
+        unsafe { asm!("dc ivac, $0" : : "r"(line) : "memory" : "volatile"); }
+    }
+    for_each_dcache_line(address, size, op);
+}
+
+// Writes dirty cache lines back and discards them.
+pub fn flush_dcache(address: usize, size: usize) {
+    fn op(line: usize) {
+        unsafe {
+            asm!("dc civac, $0" : : "r"(line) : "memory" : "volatile");
+        }
+    }
+    for_each_dcache_line(address, size, op);
+}
+
+// Keeps the id in the register reserved for the kernel, so reading it
+// needs no access to the system register describing the CPU.
+pub unsafe fn init_id() {
+    let mpidr: usize;
+    asm!("mrs $0, mpidr_el1" : "=r"(mpidr));
+    asm!("msr tpidr_el1, $0" : : "r"(mpidr & 0xFF) : : "volatile");
+}
+
+// Affinity level 0 of the current CPU (which is what QEMU varies).
+pub fn id() -> usize {
+    let id: usize;
+    unsafe { asm!("mrs $0, tpidr_el1" : "=r"(id)); }
+    id
+}
+
+// Ticks of the virtual counter which runs since reset.
+pub fn timestamp() -> u64 {
+    let ticks: u64;
+    unsafe { asm!("isb; mrs $0, cntvct_el0" : "=r"(ticks) : : : "volatile"); }
+    ticks
+}
+
+pub fn timestamp_frequency() -> u64 {
+    let frequency: u64;
+    unsafe { asm!("mrs $0, cntfrq_el0" : "=r"(frequency)); }
+    frequency
+}
+
+const DAIF_IRQ: usize = 1 << 7;
+
+// Returns whether interrupts were enabled before.
+pub fn disable_interrupts() -> bool {
+    let daif: usize;
+    unsafe {
+        asm!("mrs $0, daif; msr daifset, #2"
+             : "=r"(daif) : : "memory" : "volatile");
+    }
+    daif & DAIF_IRQ == 0
+}
+
+pub fn restore_interrupts(enabled: bool) {
+    if enabled {
+        unsafe { asm!("msr daifclr, #2" : : : "memory" : "volatile"); }
+    }
+}
+
+// Hint for spin-wait loops.
+pub fn relax() {
+    unsafe { asm!("yield" : : : "memory" : "volatile"); }
+}
+
+extern { fn __halt() -> !; }
+
+// Stops the calling CPU for good.
+pub fn halt() -> ! {
+    unsafe { __halt(); }
+}
@@ -40,7 +40,12 @@ pub fn device_tree_memory_region() -> memory::MemoryRegion {
     unsafe {
         let addr: usize = transmute(HEADER.unwrap());
         let size = u32::from_be(HEADER.unwrap().total_size) as usize;
-        memory::MemoryRegion{address:addr, size:size}
+        memory::MemoryRegion{
+            address: addr,
+            size: size,
+            kind: memory::MemoryKind::Reserved,
+            node: 0
+        }
     }
 }
 
@@ -231,6 +236,61 @@ impl<'a> Iterator for PathIter<'a> {
     }
 }
 
+// Calls a visitor for each property of the node an iterator returned by
+// PathIter points to, properties of its children are skipped.
+pub fn for_each_property<F>(mut iter: Iter, mut f: F)
+    where F: FnMut(&'static str, &'static [u8]) {
+    iter.next(); // skip the node itself
+    let mut level = 0;
+    while let Some(token) = iter.next() {
+        match token {
+            Token::BeginNode{name:_} => {
+                level += 1;
+            },
+            Token::EndNode => {
+                if level == 0 {
+                    break;
+                }
+                level -= 1;
+            },
+            Token::Property{name, value} if level == 0 => {
+                f(name, value);
+            },
+            _ => {}
+        }
+    }
+}
+
+// Calls a visitor for the reg property of each node compatible with the
+// given device.
+pub fn for_each_compatible<F>(compatible: &str, mut f: F)
+    where F: FnMut(&'static [u8]) {
+    // properties of a node go before its children, so they are all known
+    // once the next node begins or this one ends
+    let mut matched = false;
+    let mut reg: &'static [u8] = &[];
+    for token in Iter::new() {
+        match token {
+            Token::BeginNode{name:_} | Token::EndNode => {
+                if matched && !reg.is_empty() {
+                    f(reg);
+                }
+                matched = false;
+                reg = &[];
+            },
+            Token::Property{name, value} => {
+                if name.as_bytes() == "compatible".as_bytes() {
+                    matched = value.split(|&byte| byte == 0)
+                        .any(|cur| cur == compatible.as_bytes());
+                } else if name.as_bytes() == "reg".as_bytes() {
+                    reg = value;
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
 #[repr(C)]
 pub struct MemoryRegion {
     address_be: u64,
@@ -264,3 +324,27 @@ pub fn to_memory_regions<'a>(value: &'a [u8]) -> &'a [MemoryRegion] {
     let len = value.len() / size_of::<MemoryRegion>();
     unsafe { from_raw_parts(transmute(value.as_ptr()), len) }
 }
+
+// Boot arguments from /chosen (empty if there are none).
+pub fn command_line() -> &'static str {
+    let path = "/chosen/bootargs";
+    for mut iter in PathIter::new(Iter::new(), path, false) {
+        if let Some(Token::Property{name:_, value}) = iter.next() {
+            let len = value.iter().position(|&byte| byte == 0)
+                .unwrap_or(value.len());
+            return from_utf8(&value[..len]).unwrap_or("");
+        }
+    }
+    ""
+}
+
+// Reads a property value consisting of one or two cells.
+pub fn to_number(value: &[u8]) -> usize {
+    assert!(value.len() == 4 || value.len() == 8,
+        "bad number value of device tree property");
+    let mut number = 0;
+    for byte in value {
+        number = (number << 8) | *byte as usize;
+    }
+    number
+}
@@ -0,0 +1,68 @@
+use stack;
+
+const ESR_EC_SHIFT: u64 = 26;
+const ESR_EC_INSTRUCTION_ABORT: u64 = 0x21; // taken without EL change
+const ESR_EC_DATA_ABORT: u64 = 0x25; // taken without EL change
+
+// Registers saved by the exception entry code.
+#[repr(C)]
+pub struct Registers {
+    pub x: [u64; 31],
+    // SP_EL0 which is the interrupted one unless SP_EL1 was in use.
+    pub sp: u64,
+    pub elr: u64,
+    pub spsr: u64,
+    pub esr: u64,
+    pub far: u64
+}
+
+impl Registers {
+    pub fn program_counter(&self) -> usize {
+        self.elr as usize
+    }
+
+    pub fn frame_address(&self) -> usize {
+        self.x[29] as usize
+    }
+
+    pub fn dump(&self) {
+        for (row, values) in self.x.chunks(3).enumerate() {
+            match values.len() {
+                3 => klog_fatal!("x{:<2} 0x{:016X} x{:<2} 0x{:016X} \
+                    x{:<2} 0x{:016X}", row * 3, values[0], row * 3 + 1,
+                    values[1], row * 3 + 2, values[2]),
+                _ => klog_fatal!("x{:<2} 0x{:016X} sp  0x{:016X} \
+                    elr 0x{:016X}", row * 3, values[0], self.sp, self.elr)
+            }
+        }
+        klog_fatal!("spsr 0x{:08X} esr 0x{:08X} far 0x{:016X}",
+            self.spsr, self.esr, self.far);
+    }
+}
+
+static mut FAULT_REGISTERS: Option<&'static Registers> = None;
+
+extern {
+    static __vectors: u8;
+}
+
+pub unsafe fn init() {
+    asm!("msr vbar_el1, $0; isb"
+         : : "r"(&__vectors as *const u8 as usize) : : "volatile");
+}
+
+// Registers of the interrupted code if the panic is caused by a fault.
+pub fn fault_registers() -> Option<&'static Registers> {
+    unsafe { FAULT_REGISTERS }
+}
+
+#[no_mangle]
+pub unsafe extern fn __fault(kind: u64, registers: &'static Registers) -> ! {
+    FAULT_REGISTERS = Some(registers);
+    let class = registers.esr >> ESR_EC_SHIFT;
+    if class == ESR_EC_DATA_ABORT || class == ESR_EC_INSTRUCTION_ABORT {
+        stack::check_overflow(registers.far as usize);
+    }
+    panic!("exception {} at 0x{:X} accessing 0x{:X} (ESR 0x{:X})",
+        kind, registers.elr, registers.far, registers.esr);
+}
@@ -0,0 +1,64 @@
+	.set REGISTERS_SIZE, 36 * 8
+
+	.global __vectors
+
+	/* faults are fatal for now, so registers are saved for a dump only */
+	.macro vector kind
+	.balign 128
+	sub sp, sp, #REGISTERS_SIZE
+	stp x0, x1, [sp]
+	mov x0, #\kind
+	b exception
+	.endm
+
+	.text
+	.balign 2048
+__vectors:
+	/* current EL with SP_EL0 */
+	vector 0
+	vector 1
+	vector 2
+	vector 3
+	/* current EL with SP_EL1 */
+	vector 4
+	vector 5
+	vector 6
+	vector 7
+	/* lower EL using aarch64 */
+	vector 8
+	vector 9
+	vector 10
+	vector 11
+	/* lower EL using aarch32 */
+	vector 12
+	vector 13
+	vector 14
+	vector 15
+
+	/* saves the rest as laid out in exception::Registers */
+exception:
+	stp x2, x3, [sp, #16]
+	stp x4, x5, [sp, #32]
+	stp x6, x7, [sp, #48]
+	stp x8, x9, [sp, #64]
+	stp x10, x11, [sp, #80]
+	stp x12, x13, [sp, #96]
+	stp x14, x15, [sp, #112]
+	stp x16, x17, [sp, #128]
+	stp x18, x19, [sp, #144]
+	stp x20, x21, [sp, #160]
+	stp x22, x23, [sp, #176]
+	stp x24, x25, [sp, #192]
+	stp x26, x27, [sp, #208]
+	stp x28, x29, [sp, #224]
+	mrs x1, sp_el0
+	stp x30, x1, [sp, #240]
+	mrs x1, elr_el1
+	mrs x2, spsr_el1
+	stp x1, x2, [sp, #256]
+	mrs x1, esr_el1
+	mrs x2, far_el1
+	stp x1, x2, [sp, #272]
+	mov x1, sp
+	bl __fault
+	b __halt
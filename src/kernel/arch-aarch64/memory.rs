@@ -1,38 +1,272 @@
-use memory::{self, MemoryRegion};
+use arch::device_tree as dt;
+use arch::numa;
+use core::cmp::{max, min};
+use frame::FRAME_SIZE;
+use memory::{self, Error, MemoryKind, MemoryMap, MemoryRegion, RegionSet};
+use util::align_up;
 
-// TODO: add proper treatment of reserved memory regions
-fn detect_memory<'a>(buf: &'a mut [MemoryRegion]) -> &'a [MemoryRegion] {
-    use arch::device_tree as dt;
+// Physical memory is identity-mapped.
+pub const DIRECT_MAP_OFFSET: usize = 0;
 
-    let dtreg = dt::device_tree_memory_region();
-    let kreg = memory::kernel_memory_region();
+// Child node of /reserved-memory.
+struct ReservedNode {
+    name: &'static str,
+    reg: &'static [u8],
+    size: Option<usize>,
+    alignment: usize,
+    alloc_ranges: &'static [u8],
+    no_map: bool,
+    reusable: bool
+}
+
+impl ReservedNode {
+    fn new(name: &'static str) -> ReservedNode {
+        ReservedNode{
+            name: name,
+            reg: &[],
+            size: None,
+            alignment: FRAME_SIZE,
+            alloc_ranges: &[],
+            no_map: false,
+            reusable: false
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        if self.no_map { "no-map" }
+        else if self.reusable { "reusable" }
+        else { "reserved" }
+    }
+
+    fn memory_kind(&self) -> MemoryKind {
+        if self.no_map { MemoryKind::NoMap } else { MemoryKind::Reserved }
+    }
+}
+
+fn for_each_reserved_node(f: &mut FnMut(&ReservedNode) -> Result<(), Error>)
+                          -> Result<(), Error> {
+    let path = "/reserved-memory";
+    for mut iter in dt::PathIter::new(dt::Iter::new(), path, false) {
+        iter.next(); // skip the node itself
+        let mut level = 0;
+        let mut node = ReservedNode::new("");
+        while let Some(token) = iter.next() {
+            match token {
+                dt::Token::BeginNode{name} => {
+                    level += 1;
+                    if level == 1 {
+                        node = ReservedNode::new(name);
+                    }
+                },
+                dt::Token::EndNode => {
+                    if level == 0 {
+                        break;
+                    }
+                    if level == 1 {
+                        try!(f(&node));
+                    }
+                    level -= 1;
+                },
+                dt::Token::Property{name, value} if level == 1 => {
+                    // TODO: use `match` on names when compiler will stop
+                    // crashing on string comparisons
+                    let name = name.as_bytes();
+                    if name == "reg".as_bytes() {
+                        node.reg = value;
+                    } else if name == "size".as_bytes() {
+                        node.size = Some(dt::to_number(value));
+                    } else if name == "alignment".as_bytes() {
+                        node.alignment = max(dt::to_number(value), FRAME_SIZE);
+                    } else if name == "alloc-ranges".as_bytes() {
+                        node.alloc_ranges = value;
+                    } else if name == "no-map".as_bytes() {
+                        node.no_map = true;
+                    } else if name == "reusable".as_bytes() {
+                        node.reusable = true;
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn region(reg: &dt::MemoryRegion, kind: MemoryKind) -> MemoryRegion {
+    MemoryRegion{
+        address: reg.address() as usize,
+        size: reg.size() as usize,
+        kind: kind,
+        node: 0
+    }
+}
+
+fn for_each_region_named(f: &mut FnMut(MemoryRegion, &str)
+                                       -> Result<(), Error>)
+                         -> Result<(), Error> {
+    try!(numa::for_each_memory_node(&mut |value, node| {
+        for reg in dt::to_memory_regions(value) {
+            let available = MemoryRegion{
+                node: node,
+                ..region(reg, MemoryKind::Available)
+            };
+            try!(f(available, "memory"));
+        }
+        Ok(())
+    }));
+
+    for reg in dt::reserved_memory() {
+        try!(f(region(reg, MemoryKind::Reserved), "memreserve"));
+    }
+
+    // static regions only, dynamic ones are allocated afterwards
+    for_each_reserved_node(&mut |node| {
+        for reg in dt::to_memory_regions(node.reg) {
+            try!(f(region(reg, node.memory_kind()), node.name));
+        }
+        Ok(())
+    })
+}
+
+fn for_each_region(f: &mut FnMut(MemoryRegion) -> Result<(), Error>)
+                   -> Result<(), Error> {
+    for_each_region_named(&mut |region, _| f(region))
+}
 
-    let mut len = 0;
+fn for_each_reserved(f: &mut FnMut(MemoryRegion) -> Result<(), Error>)
+                     -> Result<(), Error> {
+    try!(f(dt::device_tree_memory_region()));
+    f(memory::kernel_memory_region())
+}
+
+fn is_ram(log: &MemoryRegion) -> bool {
     for mut iter in dt::PathIter::new(dt::Iter::new(), "/memory/reg", true) {
         if let Some(dt::Token::Property{name:_, value}) = iter.next() {
             for reg in dt::to_memory_regions(value) {
-                buf[len] = MemoryRegion{
-                    address: reg.address() as usize,
-                    size: reg.size() as usize
-                };
-
-                // TODO: replace this with more generic code 
-                if buf[len].address == dtreg.address {
-                    let diff = dtreg.size + kreg.size;
-                    buf[len].address += diff;
-                    buf[len].size -= diff;
+                let ram = region(reg, MemoryKind::Available);
+                if ram.address <= log.address && log.end() <= ram.end() {
+                    return true;
                 }
+            }
+        }
+    }
+    false
+}
+
+// Static region of the /reserved-memory/panic-log node if it is in RAM,
+// the node is reserved along with the others.
+pub fn panic_log_region() -> Option<MemoryRegion> {
+    let mut found = None;
+    let _ = for_each_reserved_node(&mut |node| {
+        let name = node.name.split('@').next().unwrap();
+        if name.as_bytes() == "panic-log".as_bytes() {
+            if let Some(reg) = dt::to_memory_regions(node.reg).first() {
+                found = Some(region(reg, node.memory_kind()));
+            }
+        }
+        Ok(())
+    });
+    match found {
+        Some(log) if log.kind == MemoryKind::NoMap => {
+            klog_warning!("panic log: 0x{:X} is not mapped", log.address);
+            None
+        },
+        Some(log) if is_ram(&log) => Some(log),
+        Some(log) => {
+            klog_warning!("panic log: 0x{:X} is not in memory", log.address);
+            None
+        },
+        None => None
+    }
+}
+
+fn log_reserved(region: MemoryRegion, source: &str) {
+    klog_debug!("reserved memory: {}KiB from 0x{:X} ({})",
+        blocks_used!(region.size, 1024), region.address, source);
+}
 
-                len += 1;
+fn find_free(set: &RegionSet, size: usize, alignment: usize,
+             within: MemoryRegion) -> Option<MemoryRegion> {
+    for reg in set.regions() {
+        let address = align_up(max(reg.address, within.address), alignment);
+        if address + size <= min(reg.end(), within.end()) {
+            return Some(MemoryRegion{
+                address: address,
+                size: size,
+                kind: MemoryKind::Reserved,
+                node: 0
+            });
+        }
+    }
+    None
+}
+
+fn allocate_reserved(map: &mut MemoryMap, node: &ReservedNode,
+                     size: usize) -> Result<(), Error> {
+    let mut found = None;
+    if node.alloc_ranges.is_empty() {
+        let whole = MemoryRegion{
+            address: 0,
+            size: !0,
+            kind: MemoryKind::Available,
+            node: 0
+        };
+        found = find_free(&map.available, size, node.alignment, whole);
+    } else {
+        for range in dt::to_memory_regions(node.alloc_ranges) {
+            let within = region(range, MemoryKind::Available);
+            found = find_free(&map.available, size, node.alignment, within);
+            if found.is_some() {
+                break;
             }
         }
     }
 
-    &buf[..len]
+    match found {
+        Some(found) => {
+            let region = MemoryRegion{kind: node.memory_kind(), ..found};
+            log_reserved(region, node.name);
+            map.reserve(region)
+        },
+        None => {
+            klog_warning!("failed to allocate reserved memory for {}",
+                node.name);
+            Ok(())
+        }
+    }
 }
 
-pub unsafe fn init() {
-    use config::MEMORY_REGIONS_MAX;
-    let mut buf = [MemoryRegion{address:0, size:0}; MEMORY_REGIONS_MAX];
-    memory::init(detect_memory(&mut buf[..]));
+fn is_dynamic(node: &ReservedNode) -> bool {
+    node.reg.is_empty() && node.size.is_some()
+}
+
+pub unsafe fn init() -> Result<(), Error> {
+    numa::init();
+
+    let mut dynamic = 0;
+    try!(for_each_reserved_node(&mut |node| {
+        if is_dynamic(node) { dynamic += 1; }
+        Ok(())
+    }));
+
+    let mut map = try!(MemoryMap::bootstrap(for_each_region,
+                                            for_each_reserved, dynamic));
+    try!(for_each_region_named(&mut |region, source| {
+        if region.kind != MemoryKind::Available {
+            log_reserved(region, source);
+        }
+        Ok(())
+    }));
+    try!(for_each_reserved_node(&mut |node| {
+        klog_debug!("reserved memory node {} is {}", node.name, node.kind());
+        match node.size {
+            Some(size) if is_dynamic(node) =>
+                allocate_reserved(&mut map, node, size),
+            _ => Ok(())
+        }
+    }));
+
+    map.available.align(FRAME_SIZE);
+    memory::init(map);
+    Ok(())
 }
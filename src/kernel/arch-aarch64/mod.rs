@@ -1,3 +1,7 @@
 pub mod boot;
+pub mod cpu;
 pub mod device_tree;
+pub mod exception;
 pub mod memory;
+pub mod numa;
+pub mod paging;
@@ -0,0 +1,83 @@
+use arch::device_tree as dt;
+use core::mem::transmute;
+use core::slice::from_raw_parts;
+use memory::Error;
+use numa;
+
+// Node of a device tree node having numa-node-id (zero if it is absent).
+fn node_id(value: Option<&[u8]>) -> usize {
+    match value {
+        Some(value) => {
+            let node = dt::to_number(value);
+            if numa::add_node(node) { node } else { 0 }
+        },
+        None => 0
+    }
+}
+
+// Calls a visitor for the reg property of each memory node with its node.
+pub fn for_each_memory_node(f: &mut FnMut(&'static [u8], usize)
+                                          -> Result<(), Error>)
+                            -> Result<(), Error> {
+    for iter in dt::PathIter::new(dt::Iter::new(), "/memory", true) {
+        let (mut reg, mut node) = (None, None);
+        // TODO: use `match` on names when compiler will stop
+        // crashing on string comparisons
+        dt::for_each_property(iter, |name, value| {
+            if name.as_bytes() == "reg".as_bytes() {
+                reg = Some(value);
+            } else if name.as_bytes() == "numa-node-id".as_bytes() {
+                node = Some(value);
+            }
+        });
+        if let Some(reg) = reg {
+            try!(f(reg, node_id(node)));
+        }
+    }
+    Ok(())
+}
+
+fn init_cpus() {
+    for iter in dt::PathIter::new(dt::Iter::new(), "/cpus/cpu", true) {
+        let (mut reg, mut node) = (None, None);
+        dt::for_each_property(iter, |name, value| {
+            if name.as_bytes() == "reg".as_bytes() {
+                reg = Some(value);
+            } else if name.as_bytes() == "numa-node-id".as_bytes() {
+                node = Some(value);
+            }
+        });
+        if let Some(reg) = reg {
+            // the same affinity bits as cpu::id takes
+            numa::set_cpu_node(dt::to_number(reg) & 0xFF, node_id(node));
+        }
+    }
+}
+
+fn init_distances() {
+    let path = "/distance-map/distance-matrix";
+    for mut iter in dt::PathIter::new(dt::Iter::new(), path, true) {
+        if let Some(dt::Token::Property{name:_, value}) = iter.next() {
+            // (from, to, distance) triplets of cells
+            let cells: &[u32] = unsafe {
+                from_raw_parts(transmute(value.as_ptr()), value.len() / 4)
+            };
+            for entry in cells.chunks(3) {
+                if entry.len() == 3 {
+                    numa::set_distance(u32::from_be(entry[0]) as usize,
+                                       u32::from_be(entry[1]) as usize,
+                                       u32::from_be(entry[2]) as usize);
+                }
+            }
+        }
+    }
+}
+
+// Discovers nodes of CPUs and distances between nodes.
+pub fn init() {
+    // count nodes having memory only
+    let _ = for_each_memory_node(&mut |_, _| Ok(()));
+    init_cpus();
+    init_distances();
+    numa::dump();
+}
@@ -0,0 +1,175 @@
+use arch::device_tree as dt;
+use dma;
+use memory::{self, MemoryKind};
+use paging::{self, Attributes, Cache, PageSize, MMIO, RAM};
+
+const DESC_VALID: u64 = 1 << 0;
+const DESC_TABLE: u64 = 1 << 1; // also marks level 3 pages
+const DESC_ATTR_INDEX_SHIFT: u64 = 2;
+const DESC_AP_RO: u64 = 1 << 7;
+const DESC_SH_INNER: u64 = 3 << 8;
+const DESC_AF: u64 = 1 << 10;
+const DESC_PXN: u64 = 1 << 53;
+const DESC_UXN: u64 = 1 << 54;
+const DESC_ADDRESS_MASK: u64 = 0x0000_FFFF_FFFF_F000;
+const DESC_FLAGS_MASK: u64 = !DESC_ADDRESS_MASK & !DESC_TABLE;
+
+// Memory attributes in the order of MAIR_EL1 fields.
+const MAIR_DEVICE_NGNRNE: u64 = 0x00;
+const MAIR_NORMAL_WB: u64 = 0xFF;
+const MAIR_NORMAL_WT: u64 = 0xBB;
+const MAIR_NORMAL_NC: u64 = 0x44;
+
+const TCR_T0SZ: u64 = 64 - 39; // walks start at level 1
+const TCR_IRGN0_WBWA: u64 = 1 << 8;
+const TCR_ORGN0_WBWA: u64 = 1 << 10;
+const TCR_SH0_INNER: u64 = 3 << 12;
+const TCR_EPD1: u64 = 1 << 23;
+const TCR_IPS_SHIFT: u64 = 32;
+
+const SCTLR_M: u64 = 1 << 0;
+const SCTLR_C: u64 = 1 << 2;
+const SCTLR_I: u64 = 1 << 12;
+
+// Level 1 of the architecture counted from the bottom (see TCR_T0SZ).
+pub const ROOT_LEVEL: usize = 2;
+
+fn attributes_flags(attrs: Attributes) -> u64 {
+    let mut flags = DESC_VALID | DESC_AF | DESC_UXN;
+    if !attrs.write { flags |= DESC_AP_RO; }
+    if !attrs.execute { flags |= DESC_PXN; }
+    let index = match attrs.cache {
+        Cache::Device => 0,
+        Cache::WriteBack => 1,
+        Cache::WriteThrough => 2,
+        Cache::Uncached => 3
+    };
+    flags |= index << DESC_ATTR_INDEX_SHIFT;
+    if attrs.cache != Cache::Device { flags |= DESC_SH_INNER; }
+    flags
+}
+
+fn size_flags(size: PageSize) -> u64 {
+    if size == PageSize::Small { DESC_TABLE } else { 0 }
+}
+
+pub fn is_valid(entry: u64) -> bool {
+    entry & DESC_VALID != 0
+}
+
+// Whether a valid entry at the level maps a block or a page rather than
+// a table.
+pub fn is_page(entry: u64, level: usize) -> bool {
+    level == 0 || entry & DESC_TABLE == 0
+}
+
+pub fn entry_address(entry: u64) -> usize {
+    (entry & DESC_ADDRESS_MASK) as usize
+}
+
+pub fn table_entry(phys: usize) -> u64 {
+    phys as u64 | DESC_VALID | DESC_TABLE
+}
+
+pub fn page_entry(phys: usize, size: PageSize, attrs: Attributes) -> u64 {
+    phys as u64 | attributes_flags(attrs) | size_flags(size)
+}
+
+// Flags of a page entry converted for a page of another size.
+pub fn page_flags(entry: u64, size: PageSize) -> u64 {
+    (entry & DESC_FLAGS_MASK) | size_flags(size)
+}
+
+// Changes a valid entry with break-before-make: the old entry is removed
+// from TLBs before the new one is written, as both of them must never be
+// cached at once. Memory the entry maps is inaccessible meanwhile, so it
+// must not be the code or the stack in use (the kernel image is mapped
+// with pages before the MMU is enabled).
+pub unsafe fn replace_entry(entry: *mut u64, virt: usize, value: u64) {
+    *entry = 0;
+    invalidate(virt);
+    *entry = value;
+    asm!("dsb ishst; isb" : : : "memory" : "volatile");
+}
+
+// Tables are accessed by physical addresses until the MMU is enabled and
+// all of memory is identity-mapped afterwards.
+pub fn tables_limit() -> usize {
+    !0
+}
+
+pub fn huge_pages_supported() -> bool {
+    true
+}
+
+pub fn invalidate(virt: usize) {
+    unsafe {
+        asm!("dsb ishst; tlbi vaae1is, $0; dsb ish; isb"
+             : : "r"(virt >> 12) : "memory" : "volatile");
+    }
+}
+
+pub fn invalidate_all() {
+    unsafe {
+        asm!("dsb ishst; tlbi vmalle1is; dsb ish; isb"
+             : : : "memory" : "volatile");
+    }
+}
+
+// Devices accessed by the kernel, the UART and the interrupt controller.
+const DEVICES: [&'static str; 4] = [
+    "arm,pl011",
+    "arm,cortex-a15-gic",
+    "arm,gic-400",
+    "arm,gic-v3"
+];
+
+fn map_devices() {
+    for compatible in DEVICES.iter() {
+        dt::for_each_compatible(compatible, |value| {
+            for reg in dt::to_memory_regions(value) {
+                unsafe {
+                    paging::map_direct(reg.address() as usize,
+                                       reg.size() as usize, MMIO);
+                }
+            }
+        });
+    }
+}
+
+unsafe fn enable(root: usize) {
+    let mair = MAIR_DEVICE_NGNRNE | MAIR_NORMAL_WB << 8 |
+        MAIR_NORMAL_WT << 16 | MAIR_NORMAL_NC << 24;
+
+    let mmfr0: u64;
+    asm!("mrs $0, id_aa64mmfr0_el1" : "=r"(mmfr0));
+    let tcr = TCR_T0SZ | TCR_IRGN0_WBWA | TCR_ORGN0_WBWA | TCR_SH0_INNER |
+        TCR_EPD1 | (mmfr0 & 0x7) << TCR_IPS_SHIFT;
+
+    asm!("msr mair_el1, $0; msr tcr_el1, $1; msr ttbr0_el1, $2; isb"
+         : : "r"(mair), "r"(tcr), "r"(root) : "memory" : "volatile");
+    invalidate_all();
+
+    let mut sctlr: u64;
+    asm!("mrs $0, sctlr_el1" : "=r"(sctlr));
+    sctlr |= SCTLR_M | SCTLR_C | SCTLR_I;
+    asm!("msr sctlr_el1, $0; isb" : : "r"(sctlr) : "memory" : "volatile");
+}
+
+pub unsafe fn init() {
+    let root = paging::create_root();
+
+    // reservations marked no-map are left out
+    for region in memory::memory_map() {
+        if region.kind == MemoryKind::Available ||
+            region.kind == MemoryKind::Reserved {
+            paging::map_direct(region.address, region.size, RAM);
+        }
+    }
+    map_devices();
+    paging::map_kernel();
+    dma::init();
+
+    enable(root);
+    klog_debug!("paging: MMU enabled, translation table at 0x{:X}", root);
+}
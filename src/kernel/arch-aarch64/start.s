@@ -1,15 +1,31 @@
 	.set CPACR_EL1_FPEN, 0b11 << 20
 
+	.set STACK_GUARD_SIZE, 4096
 	.set BOOT_STACK_SIZE, 32 * 1024
+	.set EMERGENCY_STACK_SIZE, 16 * 1024
 
+	.global __boot_stack_guard
 	.global __boot_stack
+	.global __boot_stack_top
+	.global __emergency_stack_guard
+	.global __emergency_stack_top
 	.global __start
 	.global __halt
 
 	.bss
-	.balign 16
+	.balign 4096
+	/* unmapped once paging is set to catch boot stack overflows */
+__boot_stack_guard:
+	.fill STACK_GUARD_SIZE
 __boot_stack:
 	.fill BOOT_STACK_SIZE
+__boot_stack_top:
+
+	/* exceptions are taken here as the faulting stack can be unusable */
+__emergency_stack_guard:
+	.fill STACK_GUARD_SIZE
+	.fill EMERGENCY_STACK_SIZE
+__emergency_stack_top:
 
 	.text
 __start:
@@ -17,11 +33,15 @@ __start:
 	mov x0, #CPACR_EL1_FPEN
 	msr cpacr_el1, x0
 
-	/* set stack */
-	adr x0, __boot_stack
-	add sp, x0, #BOOT_STACK_SIZE
+	/* use SP_EL1 for exceptions only and SP_EL0 for everything else */
+	ldr x0, =__emergency_stack_top
+	mov sp, x0
+	msr spsel, #0
+	ldr x0, =__boot_stack_top
+	mov sp, x0
 
-	/* call the Rust entry point */
+	/* call the Rust entry point, a zero frame ends backtraces */
+	mov x29, #0
 	bl __boot
 
 __halt:
@@ -0,0 +1,116 @@
+use core::mem::size_of;
+use core::slice::from_raw_parts;
+use memory;
+
+// Real mode segment of the extended BIOS data area is stored here.
+const EBDA_SEGMENT_POINTER: usize = 0x40E;
+const EBDA_SEARCH_SIZE: usize = 1024;
+
+const BIOS_AREA_BEGIN: usize = 0xE0000;
+const BIOS_AREA_END: usize = 0x100000;
+
+const RSDP_SIGNATURE: &'static [u8; 8] = b"RSD PTR ";
+const RSDP_ALIGN: usize = 16;
+
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // These are only valid if revision is 2 or higher.
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3]
+}
+
+// Common header of all system description tables.
+#[repr(C, packed)]
+pub struct Header {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32
+}
+
+impl Header {
+    // Table contents following the header.
+    pub fn data(&self) -> &'static [u8] {
+        let address = self as *const Header as usize + size_of::<Header>();
+        let len = self.length as usize - size_of::<Header>();
+        unsafe { from_raw_parts(address as *const u8, len) }
+    }
+}
+
+static mut RSDP: Option<&'static Rsdp> = None;
+
+fn is_valid(address: usize, size: usize) -> bool {
+    let ptr = memory::phys_to_virt(address) as *const u8;
+    let bytes = unsafe { from_raw_parts(ptr, size) };
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+fn search_rsdp(from: usize, to: usize) -> Option<&'static Rsdp> {
+    let mut address = from;
+    while address + size_of::<Rsdp>() <= to {
+        let rsdp = unsafe {
+            &*(memory::phys_to_virt(address) as *const Rsdp)
+        };
+        if &rsdp.signature == RSDP_SIGNATURE && is_valid(address, 20) {
+            return Some(rsdp);
+        }
+        address += RSDP_ALIGN;
+    }
+    None
+}
+
+// Looks for the root pointer in places defined by the specification.
+pub unsafe fn init() {
+    let pointer = memory::phys_to_virt(EBDA_SEGMENT_POINTER) as *const u16;
+    let ebda = (*pointer as usize) << 4;
+    RSDP = search_rsdp(ebda, ebda + EBDA_SEARCH_SIZE)
+        .or_else(|| search_rsdp(BIOS_AREA_BEGIN, BIOS_AREA_END));
+    match RSDP {
+        Some(rsdp) => klog_debug!("acpi: root pointer at 0x{:X}",
+            memory::virt_to_phys(rsdp as *const Rsdp as usize)),
+        None => klog_warning!("acpi: no root pointer found")
+    }
+}
+
+unsafe fn table(address: usize) -> &'static Header {
+    &*(memory::phys_to_virt(address) as *const Header)
+}
+
+// Returns the first valid table with the given signature.
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static Header> {
+    let rsdp = match unsafe { RSDP } {
+        Some(rsdp) => rsdp,
+        None => return None
+    };
+
+    // prefer XSDT with 64-bit entries if present
+    let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (rsdp.xsdt_address as usize, 8)
+    } else {
+        (rsdp.rsdt_address as usize, 4)
+    };
+
+    let entries = unsafe { table(root) }.data();
+    for entry in entries.chunks(entry_size) {
+        let address = entry.iter().rev()
+            .fold(0, |address, &byte| address << 8 | byte as usize);
+        let table = unsafe { table(address) };
+        if &table.signature == signature &&
+            is_valid(address, table.length as usize) {
+            return Some(table);
+        }
+    }
+    None
+}
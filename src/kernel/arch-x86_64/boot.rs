@@ -1,10 +1,17 @@
+use arch::cpu;
+use arch::exception;
 use arch::memory;
 use arch::multiboot;
+use arch::paging;
 use klog;
+use memory::phys_to_virt;
+use panic_log;
+use stack;
+use trace;
 
 // TODO: replace with a proper serial port handling code
 fn write(s: &str) {
-    let port = 0x400 as *const u16;
+    let port = phys_to_virt(0x400) as *const u16;
     for b in s.chars() {
         unsafe {
             asm!("outb $0, $1" : : "{al}"(b as u8), "{dx}"(*port));
@@ -12,9 +19,32 @@ fn write(s: &str) {
     }
 }
 
+extern {
+    static __boot_stack_guard: u8;
+    static __boot_stack_top: u8;
+    static __emergency_stack_guard: u8;
+    static __emergency_stack_top: u8;
+}
+
 #[no_mangle]
 pub unsafe extern fn __boot(magic: u32, info_ptr: usize) {
-    klog::init(write, klog::Level::Debug);
+    cpu::init_id();
+    cpu::calibrate_timestamp();
+    klog::register(write, klog::Level::Debug);
+    exception::init();
     multiboot::init(magic, info_ptr);
-    memory::init();
+    klog::configure(multiboot::command_line());
+    trace::configure(multiboot::command_line());
+    panic_log::init();
+    if let Err(err) = memory::init() {
+        klog_fatal!("failed to detect memory: {:?}", err);
+        cpu::halt();
+    }
+    paging::init();
+    stack::register("boot", &__boot_stack_guard as *const u8 as usize,
+                    &__boot_stack_top as *const u8 as usize);
+    stack::register("emergency",
+                    &__emergency_stack_guard as *const u8 as usize,
+                    &__emergency_stack_top as *const u8 as usize);
+    trace::init(write);
 }
@@ -0,0 +1,164 @@
+use config::CPUS_MAX;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+#[inline(always)]
+pub fn frame_address() -> usize {
+    let rbp: usize;
+    unsafe { asm!("movq %rbp, $0" : "=r"(rbp)); }
+    rbp
+}
+
+// Return address of the function this gets inlined into, used to record
+// allocation sites by the debug heap.
+#[cfg(debug_heap)]
+#[inline(always)]
+pub fn return_address() -> usize {
+    unsafe { *((frame_address() + 8) as *const usize) }
+}
+
+// Caches are coherent with device memory accesses, so these do nothing.
+pub const DMA_COHERENT: bool = true;
+
+pub fn clean_dcache(_address: usize, _size: usize) {}
+
+pub fn invalidate_dcache(_address: usize, _size: usize) {}
+
+pub fn flush_dcache(_address: usize, _size: usize) {}
+
+const CPUID_MAX_LEAF: u32 = 0x00000000;
+const CPUID_FEATURES: u32 = 0x00000001;
+const CPUID_TSC: u32 = 0x00000015;
+
+const PIT_FREQUENCY: u64 = 1193182;
+const PIT_CHANNEL2_PORT: u16 = 0x42;
+const PIT_COMMAND_PORT: u16 = 0x43;
+const PIT_CHANNEL2_ONESHOT: u8 = 0xB0; // lobyte/hibyte, mode 0
+const PORT_B: u16 = 0x61;
+const PORT_B_GATE2: u8 = 1 << 0;
+const PORT_B_SPEAKER: u8 = 1 << 1;
+const PORT_B_OUT2: u8 = 1 << 5;
+
+// 10ms of PIT ticks are used to measure the TSC frequency.
+const CALIBRATION_TICKS: u64 = PIT_FREQUENCY / 100;
+// TSC ticks to wait for the PIT at most, 10ms would take this many at
+// 100GHz, so only a missing PIT exceeds it.
+const CALIBRATION_TIMEOUT: u64 = 1 << 30;
+
+const MSR_GS_BASE: u32 = 0xC000_0101;
+
+static mut TSC_FREQUENCY: u64 = 0;
+
+// Identifiers of CPUs read once by init_id, as CPUID is serializing and
+// traps to the hypervisor in virtual machines. The GS base of each CPU
+// points to its own entry.
+static mut IDS: [usize; CPUS_MAX] = [0; CPUS_MAX];
+static NEXT_ID_SLOT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// Must be called first on each CPU, before anything asks for its id.
+pub unsafe fn init_id() {
+    let slot = NEXT_ID_SLOT.fetch_add(1, Ordering::Relaxed);
+    if slot >= CPUS_MAX {
+        halt();
+    }
+    IDS[slot] = (cpuid(CPUID_FEATURES).1 >> 24) as usize;
+    let address = &IDS[slot] as *const usize as u64;
+    asm!("wrmsr" : : "{ecx}"(MSR_GS_BASE), "{eax}"(address as u32),
+                     "{edx}"((address >> 32) as u32) : : "volatile");
+}
+
+// Initial local APIC identifier of the current CPU.
+pub fn id() -> usize {
+    let id: usize;
+    unsafe { asm!("movq %gs:0, $0" : "=r"(id)); }
+    id
+}
+
+pub unsafe fn inb(port: u16) -> u8 {
+    let value: u8;
+    asm!("inb %dx, %al" : "={al}"(value) : "{dx}"(port) : : "volatile");
+    value
+}
+
+pub unsafe fn outb(port: u16, value: u8) {
+    asm!("outb %al, %dx" : : "{al}"(value), "{dx}"(port) : : "volatile");
+}
+
+fn cpuid(leaf: u32) -> (u32, u32, u32) {
+    let (eax, ebx, ecx): (u32, u32, u32);
+    unsafe {
+        asm!("cpuid" : "={eax}"(eax), "={ebx}"(ebx), "={ecx}"(ecx)
+                     : "{eax}"(leaf) : "edx");
+    }
+    (eax, ebx, ecx)
+}
+
+// Ticks of the time stamp counter which runs since reset.
+pub fn timestamp() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdtsc" : "={eax}"(low), "={edx}"(high) : : : "volatile");
+    }
+    (high as u64) << 32 | low as u64
+}
+
+// Zero until calibrate_timestamp is called.
+pub fn timestamp_frequency() -> u64 {
+    unsafe { TSC_FREQUENCY }
+}
+
+// Takes the TSC frequency from CPUID if reported, measures it using
+// the PIT otherwise. The frequency stays zero if there is no PIT.
+pub unsafe fn calibrate_timestamp() {
+    if cpuid(CPUID_MAX_LEAF).0 >= CPUID_TSC {
+        let (denominator, numerator, crystal) = cpuid(CPUID_TSC);
+        if denominator != 0 && numerator != 0 && crystal != 0 {
+            TSC_FREQUENCY = crystal as u64 * numerator as u64 /
+                denominator as u64;
+            return;
+        }
+    }
+
+    let port_b = inb(PORT_B) & !PORT_B_SPEAKER | PORT_B_GATE2;
+    outb(PORT_B, port_b);
+    outb(PIT_COMMAND_PORT, PIT_CHANNEL2_ONESHOT);
+    outb(PIT_CHANNEL2_PORT, CALIBRATION_TICKS as u8);
+    outb(PIT_CHANNEL2_PORT, (CALIBRATION_TICKS >> 8) as u8);
+
+    let start = timestamp();
+    while inb(PORT_B) & PORT_B_OUT2 == 0 {
+        if timestamp() - start > CALIBRATION_TIMEOUT {
+            klog_warning!("cpu: PIT does not count, TSC frequency unknown");
+            return;
+        }
+    }
+    TSC_FREQUENCY = (timestamp() - start) * PIT_FREQUENCY / CALIBRATION_TICKS;
+}
+
+const RFLAGS_IF: usize = 1 << 9;
+
+// Returns whether interrupts were enabled before.
+pub fn disable_interrupts() -> bool {
+    let rflags: usize;
+    unsafe {
+        asm!("pushfq; popq $0; cli" : "=r"(rflags) : : "memory" : "volatile");
+    }
+    rflags & RFLAGS_IF != 0
+}
+
+pub fn restore_interrupts(enabled: bool) {
+    if enabled {
+        unsafe { asm!("sti" : : : "memory" : "volatile"); }
+    }
+}
+
+// Hint for spin-wait loops.
+pub fn relax() {
+    unsafe { asm!("pause" : : : "memory" : "volatile"); }
+}
+
+extern { fn __halt() -> !; }
+
+// Stops the calling CPU for good.
+pub fn halt() -> ! {
+    unsafe { __halt(); }
+}
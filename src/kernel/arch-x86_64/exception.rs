@@ -0,0 +1,210 @@
+use core::mem::size_of;
+use stack;
+
+const VECTOR_DOUBLE_FAULT: u64 = 8;
+const VECTOR_PAGE_FAULT: u64 = 14;
+
+const IDT_ENTRIES: usize = 32;
+const IDT_INTERRUPT_GATE: u8 = 0x8E;
+
+const GDT_TYPE_DATA: u64 = 0x2 << 40;
+const GDT_TYPE_CODE: u64 = 0xA << 40;
+const GDT_TYPE_TSS: u64 = 0x9 << 40;
+const GDT_NONSYS: u64 = 1 << 44;
+const GDT_PRESENT: u64 = 1 << 47;
+const GDT_BITS64: u64 = 1 << 53;
+const GDT_BITS32: u64 = 1 << 54;
+
+const SEGMENT_CODE: u16 = 0x8;
+const SEGMENT_TSS: u16 = 0x18;
+
+// Interrupt stack table slot for the emergency stack (counted from 1).
+const IST_EMERGENCY: u8 = 1;
+
+#[repr(C, packed)]
+struct TaskStateSegment {
+    reserved0: u32,
+    rsp: [u64; 3],
+    reserved1: u64,
+    ist: [u64; 7],
+    reserved2: u64,
+    reserved3: u16,
+    iomap_base: u16
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct Gate {
+    offset_low: u16,
+    selector: u16,
+    ist: u8,
+    kind: u8,
+    offset_middle: u16,
+    offset_high: u32,
+    reserved: u32
+}
+
+#[repr(C, packed)]
+struct TablePointer {
+    limit: u16,
+    base: u64
+}
+
+// Registers saved by the entry code followed by what the CPU pushes for
+// exceptions with an error code.
+#[repr(C)]
+pub struct Registers {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub error: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64
+}
+
+impl Registers {
+    pub fn program_counter(&self) -> usize {
+        self.rip as usize
+    }
+
+    pub fn frame_address(&self) -> usize {
+        self.rbp as usize
+    }
+
+    pub fn dump(&self) {
+        let rows = [
+            ("rax", self.rax, "rbx", self.rbx, "rcx", self.rcx),
+            ("rdx", self.rdx, "rsi", self.rsi, "rdi", self.rdi),
+            ("rbp", self.rbp, "rsp", self.rsp, "r8 ", self.r8),
+            ("r9 ", self.r9, "r10", self.r10, "r11", self.r11),
+            ("r12", self.r12, "r13", self.r13, "r14", self.r14),
+            ("r15", self.r15, "rip", self.rip, "rfl", self.rflags),
+            ("cs ", self.cs, "ss ", self.ss, "err", self.error)
+        ];
+        for &(name0, value0, name1, value1, name2, value2) in rows.iter() {
+            klog_fatal!("{} 0x{:016X} {} 0x{:016X} {} 0x{:016X}",
+                name0, value0, name1, value1, name2, value2);
+        }
+    }
+}
+
+static mut FAULT_REGISTERS: Option<&'static Registers> = None;
+
+static mut TSS: TaskStateSegment = TaskStateSegment{
+    reserved0: 0,
+    rsp: [0; 3],
+    reserved1: 0,
+    ist: [0; 7],
+    reserved2: 0,
+    reserved3: 0,
+    iomap_base: 0
+};
+
+// Replaces the boot GDT which lacks a TSS descriptor.
+static mut GDT: [u64; 5] = [
+    0,
+    GDT_TYPE_CODE | GDT_NONSYS | GDT_PRESENT | GDT_BITS64,
+    GDT_TYPE_DATA | GDT_NONSYS | GDT_PRESENT | GDT_BITS32,
+    0, 0 // TSS
+];
+
+static mut IDT: [Gate; IDT_ENTRIES] = [Gate{
+    offset_low: 0,
+    selector: 0,
+    ist: 0,
+    kind: 0,
+    offset_middle: 0,
+    offset_high: 0,
+    reserved: 0
+}; IDT_ENTRIES];
+
+extern {
+    static __emergency_stack_top: u8;
+    fn __double_fault_entry();
+    fn __page_fault_entry();
+}
+
+unsafe fn set_tss_descriptor() {
+    let base = &TSS as *const TaskStateSegment as u64;
+    let limit = size_of::<TaskStateSegment>() as u64 - 1;
+    GDT[3] = limit & 0xFFFF | (base & 0xFF_FFFF) << 16 | GDT_TYPE_TSS |
+        GDT_PRESENT | (base >> 24 & 0xFF) << 56;
+    GDT[4] = base >> 32;
+}
+
+unsafe fn set_gate(vector: u64, handler: unsafe extern fn()) {
+    let offset = handler as usize as u64;
+    IDT[vector as usize] = Gate{
+        offset_low: offset as u16,
+        selector: SEGMENT_CODE,
+        ist: IST_EMERGENCY,
+        kind: IDT_INTERRUPT_GATE,
+        offset_middle: (offset >> 16) as u16,
+        offset_high: (offset >> 32) as u32,
+        reserved: 0
+    };
+}
+
+pub unsafe fn init() {
+    TSS.ist[IST_EMERGENCY as usize - 1] =
+        &__emergency_stack_top as *const u8 as u64;
+    TSS.iomap_base = size_of::<TaskStateSegment>() as u16;
+    set_tss_descriptor();
+
+    let gdti = TablePointer{
+        limit: (size_of::<[u64; 5]>() - 1) as u16,
+        base: &GDT as *const [u64; 5] as u64
+    };
+    asm!("lgdt ($0); ltr $1"
+         : : "r"(&gdti), "r"(SEGMENT_TSS) : "memory" : "volatile");
+
+    set_gate(VECTOR_DOUBLE_FAULT, __double_fault_entry);
+    set_gate(VECTOR_PAGE_FAULT, __page_fault_entry);
+    let idti = TablePointer{
+        limit: (size_of::<[Gate; IDT_ENTRIES]>() - 1) as u16,
+        base: &IDT as *const [Gate; IDT_ENTRIES] as u64
+    };
+    asm!("lidt ($0)" : : "r"(&idti) : "memory" : "volatile");
+}
+
+fn fault_address() -> usize {
+    let cr2: usize;
+    unsafe { asm!("movq %cr2, $0" : "=r"(cr2)); }
+    cr2
+}
+
+// Registers of the interrupted code if the panic is caused by a fault.
+pub fn fault_registers() -> Option<&'static Registers> {
+    unsafe { FAULT_REGISTERS }
+}
+
+#[no_mangle]
+pub unsafe extern fn __fault(vector: u64,
+                             registers: &'static Registers) -> ! {
+    FAULT_REGISTERS = Some(registers);
+    // a double fault is most likely caused by a page fault here as well
+    let address = fault_address();
+    stack::check_overflow(address);
+    let name = if vector == VECTOR_PAGE_FAULT {
+        "page fault"
+    } else {
+        "double fault"
+    };
+    panic!("{} at 0x{:X} accessing 0x{:X} (error 0x{:X})",
+        name, registers.rip, address, registers.error);
+}
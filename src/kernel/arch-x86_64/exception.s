@@ -0,0 +1,39 @@
+	.set VECTOR_DOUBLE_FAULT, 8
+	.set VECTOR_PAGE_FAULT, 14
+
+	.global __double_fault_entry
+	.global __page_fault_entry
+
+	/* saves registers below the error code pushed by the CPU as they are
+	   laid out in exception::Registers */
+	.macro fault_entry vector
+	pushq %r15
+	pushq %r14
+	pushq %r13
+	pushq %r12
+	pushq %r11
+	pushq %r10
+	pushq %r9
+	pushq %r8
+	pushq %rbp
+	pushq %rdi
+	pushq %rsi
+	pushq %rdx
+	pushq %rcx
+	pushq %rbx
+	pushq %rax
+	movq $\vector, %rdi
+	movq %rsp, %rsi
+	/* 21 quadwords are pushed, so realign the stack for the call */
+	subq $8, %rsp
+	call __fault
+	jmp __halt
+	.endm
+
+	/* both run on the emergency stack with an error code pushed */
+	.text
+__double_fault_entry:
+	fault_entry VECTOR_DOUBLE_FAULT
+
+__page_fault_entry:
+	fault_entry VECTOR_PAGE_FAULT
@@ -1,34 +1,60 @@
-use memory::{self, MemoryRegion};
+use arch::multiboot;
+use arch::numa;
+use frame::FRAME_SIZE;
+use memory::{self, Error, MemoryKind, MemoryMap, MemoryRegion};
 
-// TODO: add proper treatment of reserved memory regions
-fn detect_memory<'a>(buf: &'a mut [MemoryRegion]) -> &'a [MemoryRegion] {
-    use arch::multiboot;
+// Physical memory is mapped from the start of the higher half, the lower
+// half is left unmapped.
+pub const DIRECT_MAP_OFFSET: usize = 0xFFFF_8000_0000_0000;
 
-    let kreg = memory::kernel_memory_region();
+extern {
+    static __panic_log_begin: u8;
+    static __panic_log_end: u8;
+}
+
+// Reserved by the linker script right below the kernel image, where boot
+// loaders neither clear memory nor place boot information.
+pub fn panic_log_region() -> Option<MemoryRegion> {
+    let begin = &__panic_log_begin as *const u8 as usize;
+    let end = &__panic_log_end as *const u8 as usize;
+    Some(MemoryRegion{
+        address: begin,
+        size: end - begin,
+        kind: MemoryKind::Reserved,
+        node: 0
+    })
+}
 
-    let mut len = 0;
+fn for_each_region(f: &mut FnMut(MemoryRegion) -> Result<(), Error>)
+                   -> Result<(), Error> {
     for reg in multiboot::MemoryMapIter::new() {
-        if reg.kind == multiboot::MEM_KIND_AVAILABLE {
-            buf[len] = MemoryRegion{
-                address: reg.base_addr as usize,
-                size: reg.length as usize
-            };
+        try!(numa::split_by_node(reg.to_memory_region(), f));
+    }
+    Ok(())
+}
 
-            // TODO: replace this with more generic code 
-            if buf[len].address == kreg.address {
-                buf[len].address += kreg.size;
-                buf[len].size -= kreg.size;
-            }
+fn for_each_reserved(f: &mut FnMut(MemoryRegion) -> Result<(), Error>)
+                     -> Result<(), Error> {
+    try!(f(memory::kernel_memory_region()));
+    try!(f(panic_log_region().unwrap()));
+    try!(f(multiboot::info_memory_region()));
+    try!(f(multiboot::command_line_region()));
+    try!(f(multiboot::memory_map_region()));
 
-            len += 1;
-        }
+    let modules = multiboot::ModuleIter::new();
+    try!(f(modules.table_memory_region()));
+    for module in modules {
+        try!(f(module.memory_region()));
+        try!(f(module.string_memory_region()));
     }
-
-    &buf[..len]
+    Ok(())
 }
 
-pub unsafe fn init() {
-    use config::MEMORY_REGIONS_MAX;
-    let mut buf = [MemoryRegion{address:0, size:0}; MEMORY_REGIONS_MAX];
-    memory::init(detect_memory(&mut buf[..]));
+pub unsafe fn init() -> Result<(), Error> {
+    numa::init();
+    let mut map = try!(MemoryMap::bootstrap(for_each_region,
+                                            for_each_reserved, 0));
+    map.available.align(FRAME_SIZE);
+    memory::init(map);
+    Ok(())
 }
@@ -1,3 +1,8 @@
+pub mod acpi;
 pub mod boot;
+pub mod cpu;
+pub mod exception;
 pub mod memory;
 pub mod multiboot;
+pub mod numa;
+pub mod paging;
@@ -1,44 +1,25 @@
 #![allow(dead_code)]
 
-use core::mem::transmute;
-
-// The magic field should contain this.
-const HEADER_MAGIC: u32 = 0x1BADB002;
-
-// Must pass memory information to OS.
-const HEADER_MEMORY_INFO: u32 = 0x00000002;
+use core::mem::{size_of, transmute};
+use core::slice::from_raw_parts;
+use core::str::from_utf8;
+use libc::strlen;
+use memory;
 
 // This should be in %eax.
 const BOOTLOADER_MAGIC: u32 = 0x2BADB002;
 
-// Is there a full memory map?
-const INFO_MEMORY_MAP: u32 = 0x00000040;
+// Is there a command line?
+const INFO_CMDLINE: u32 = 0x00000004;
 
-#[repr(C)]
-struct Header {
-    // Must be MAGIC - see above.
-    magic: u32,
-    // Feature flags.
-    flags: u32,
-
-    // The above fields plus this one must equal 0 mod 2^32.
-    checksum: u32,
+// Are there modules to do something with?
+const INFO_MODS: u32 = 0x00000008;
 
-    // These are only valid if AOUT_KLUDGE is set.
-    header_addr: u32,
-    load_addr: u32,
-    load_end_addr: u32,
-    bss_end_addr: u32,
-    entry_addr: u32,
-
-    // These are only valid if VIDEO_MODE is set.
-    mode_type: u32,
-    width: u32,
-    height: u32,
-    depth: u32
-}
+// Is there a full memory map?
+const INFO_MEMORY_MAP: u32 = 0x00000040;
 
-unsafe impl Sync for Header {}
+// Is there framebuffer info?
+const INFO_FRAMEBUFFER: u32 = 0x00001000;
 
 #[repr(C)]
 struct ElfSectionHeaderTable {
@@ -93,26 +74,15 @@ struct Info {
     vbe_interface_seg: u16,
     vbe_interface_off: u16,
     vbe_interface_len: u16,
-}
 
-const HEADER_FLAGS: u32 = HEADER_MEMORY_INFO;
-
-#[linkage="external"]
-#[link_section= ".header"]
-static MULTIBOOT_HEADER: Header = Header {
-    magic: HEADER_MAGIC,
-    flags: HEADER_FLAGS,
-    checksum: (-((HEADER_MAGIC + HEADER_FLAGS) as i32) as u32),
-    header_addr: 0,
-    load_addr: 0,
-    load_end_addr: 0,
-    bss_end_addr: 0,
-    entry_addr: 0,
-    mode_type: 0,
-    width: 0,
-    height: 0,
-    depth: 0
-};
+    // Framebuffer
+    framebuffer_addr: u64,
+    framebuffer_pitch: u32,
+    framebuffer_width: u32,
+    framebuffer_height: u32,
+    framebuffer_bpp: u8,
+    framebuffer_type: u8,
+}
 
 static mut INFO: Option<*const Info> = None;
 
@@ -120,13 +90,85 @@ pub unsafe fn init(magic: u32, info_ptr: usize) {
     if magic != BOOTLOADER_MAGIC {
         panic!("bad multiboot magic");
     }
-    INFO = Some(info_ptr as *const Info);
+    INFO = Some(memory::phys_to_virt(info_ptr) as *const Info);
     if (*INFO.unwrap()).flags & INFO_MEMORY_MAP == 0 {
         panic!("no memory map in multiboot info");
     }
 }
 
+pub fn command_line() -> &'static str {
+    unsafe {
+        let info = INFO.unwrap();
+        if (*info).flags & INFO_CMDLINE == 0 {
+            return "";
+        }
+        let ptr = memory::phys_to_virt((*info).cmdline as usize) as *const u8;
+        from_utf8(from_raw_parts(ptr, strlen(ptr))).unwrap_or("")
+    }
+}
+
+pub fn command_line_region() -> memory::MemoryRegion {
+    unsafe {
+        let info = INFO.unwrap();
+        let (address, size) = if (*info).flags & INFO_CMDLINE != 0 {
+            let address = (*info).cmdline as usize;
+            (address, strlen(memory::phys_to_virt(address) as *const u8) + 1)
+        } else {
+            (0, 0)
+        };
+        memory::MemoryRegion{
+            address: address,
+            size: size,
+            kind: memory::MemoryKind::Reserved,
+            node: 0
+        }
+    }
+}
+
+pub fn info_memory_region() -> memory::MemoryRegion {
+    unsafe {
+        memory::MemoryRegion{
+            address: memory::virt_to_phys(INFO.unwrap() as usize),
+            size: size_of::<Info>(),
+            kind: memory::MemoryKind::Reserved,
+            node: 0
+        }
+    }
+}
+
+// Graphics or text mode framebuffer set up by the boot loader.
+pub fn framebuffer_region() -> Option<memory::MemoryRegion> {
+    unsafe {
+        let info = INFO.unwrap();
+        if (*info).flags & INFO_FRAMEBUFFER == 0 {
+            return None;
+        }
+        Some(memory::MemoryRegion{
+            address: (*info).framebuffer_addr as usize,
+            size: (*info).framebuffer_pitch as usize *
+                (*info).framebuffer_height as usize,
+            kind: memory::MemoryKind::Reserved,
+            node: 0
+        })
+    }
+}
+
+pub fn memory_map_region() -> memory::MemoryRegion {
+    unsafe {
+        memory::MemoryRegion{
+            address: (*INFO.unwrap()).mmap_addr as usize,
+            size: (*INFO.unwrap()).mmap_length as usize,
+            kind: memory::MemoryKind::Reserved,
+            node: 0
+        }
+    }
+}
+
 pub const MEM_KIND_AVAILABLE: u32 = 1;
+pub const MEM_KIND_RESERVED: u32 = 2;
+pub const MEM_KIND_ACPI_RECLAIMABLE: u32 = 3;
+pub const MEM_KIND_NVS: u32 = 4;
+pub const MEM_KIND_BADRAM: u32 = 5;
 
 #[repr(packed)]
 #[derive(Clone, Copy, Debug)]
@@ -137,13 +179,34 @@ pub struct MemoryRegion {
     pub kind: u32
 }
 
+impl MemoryRegion {
+    pub fn to_memory_region(&self) -> memory::MemoryRegion {
+        memory::MemoryRegion{
+            address: self.base_addr as usize,
+            size: self.length as usize,
+            kind: match self.kind {
+                MEM_KIND_AVAILABLE => memory::MemoryKind::Available,
+                MEM_KIND_ACPI_RECLAIMABLE =>
+                    memory::MemoryKind::AcpiReclaimable,
+                MEM_KIND_NVS => memory::MemoryKind::AcpiNvs,
+                MEM_KIND_BADRAM => memory::MemoryKind::Bad,
+                _ => memory::MemoryKind::Reserved
+            },
+            node: 0
+        }
+    }
+}
+
 pub struct MemoryMapIter {
     ptr: usize
 }
 
 impl MemoryMapIter {
     pub fn new() -> MemoryMapIter {
-        unsafe { MemoryMapIter{ptr: (*INFO.unwrap()).mmap_addr as usize} }
+        unsafe {
+            let address = (*INFO.unwrap()).mmap_addr as usize;
+            MemoryMapIter{ptr: memory::phys_to_virt(address)}
+        }
     }
 }
 
@@ -152,7 +215,8 @@ impl Iterator for MemoryMapIter {
 
     fn next(&mut self) -> Option<MemoryRegion> {
         unsafe {
-            let mmap_ptr = (*INFO.unwrap()).mmap_addr as usize;
+            let mmap_ptr =
+                memory::phys_to_virt((*INFO.unwrap()).mmap_addr as usize);
             let mmap_len = (*INFO.unwrap()).mmap_length as usize;
 
             if self.ptr >= mmap_ptr + mmap_len {
@@ -165,3 +229,85 @@ impl Iterator for MemoryMapIter {
         }
     }
 }
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Module {
+    pub mod_start: u32,
+    pub mod_end: u32,
+    pub string: u32,
+    reserved: u32
+}
+
+impl Module {
+    pub fn memory_region(&self) -> memory::MemoryRegion {
+        memory::MemoryRegion{
+            address: self.mod_start as usize,
+            size: (self.mod_end - self.mod_start) as usize,
+            kind: memory::MemoryKind::Reserved,
+            node: 0
+        }
+    }
+
+    pub fn string_memory_region(&self) -> memory::MemoryRegion {
+        let (address, size) = if self.string != 0 {
+            let address = self.string as usize;
+            let ptr = memory::phys_to_virt(address) as *const u8;
+            (address, unsafe { strlen(ptr) } + 1)
+        } else {
+            (0, 0)
+        };
+        memory::MemoryRegion{
+            address: address,
+            size: size,
+            kind: memory::MemoryKind::Reserved,
+            node: 0
+        }
+    }
+}
+
+pub struct ModuleIter {
+    ptr: *const Module,
+    left: usize
+}
+
+impl ModuleIter {
+    pub fn new() -> ModuleIter {
+        unsafe {
+            let info = INFO.unwrap();
+            let left = if (*info).flags & INFO_MODS != 0 {
+                (*info).mods_count as usize
+            } else {
+                0
+            };
+            let address = (*info).mods_addr as usize;
+            ModuleIter{ptr: memory::phys_to_virt(address) as *const Module,
+                       left: left}
+        }
+    }
+
+    pub fn table_memory_region(&self) -> memory::MemoryRegion {
+        memory::MemoryRegion{
+            address: memory::virt_to_phys(self.ptr as usize),
+            size: self.left * size_of::<Module>(),
+            kind: memory::MemoryKind::Reserved,
+            node: 0
+        }
+    }
+}
+
+impl Iterator for ModuleIter {
+    type Item = Module;
+
+    fn next(&mut self) -> Option<Module> {
+        if self.left == 0 {
+            return None;
+        }
+        unsafe {
+            let module = *self.ptr;
+            self.ptr = self.ptr.offset(1);
+            self.left -= 1;
+            Some(module)
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use arch::acpi;
+use core::cmp::min;
+use memory::{Error, MemoryKind, MemoryRegion};
+use numa;
+
+// Static resource affinity table entries follow these reserved fields.
+const SRAT_RESERVED_SIZE: usize = 12;
+
+const SRAT_PROCESSOR: u8 = 0;
+const SRAT_MEMORY: u8 = 1;
+const SRAT_X2APIC: u8 = 2;
+
+const SRAT_ENABLED: u32 = 1;
+
+#[repr(C, packed)]
+struct ProcessorAffinity {
+    kind: u8,
+    length: u8,
+    domain_low: u8,
+    apic_id: u8,
+    flags: u32,
+    sapic_eid: u8,
+    domain_high: [u8; 3],
+    clock_domain: u32
+}
+
+#[repr(C, packed)]
+struct MemoryAffinity {
+    kind: u8,
+    length: u8,
+    domain: u32,
+    reserved0: u16,
+    base_low: u32,
+    base_high: u32,
+    length_low: u32,
+    length_high: u32,
+    reserved1: u32,
+    flags: u32,
+    reserved2: u64
+}
+
+#[repr(C, packed)]
+struct X2ApicAffinity {
+    kind: u8,
+    length: u8,
+    reserved0: u16,
+    domain: u32,
+    x2apic_id: u32,
+    flags: u32,
+    clock_domain: u32,
+    reserved1: u32
+}
+
+static mut SRAT: Option<&'static acpi::Header> = None;
+
+// Proximity domains are used as node numbers as they are.
+fn for_each_srat_entry<F>(mut f: F) where F: FnMut(u8, usize) {
+    let srat = match unsafe { SRAT } {
+        Some(srat) => srat.data(),
+        None => return
+    };
+    let mut offset = SRAT_RESERVED_SIZE;
+    while offset + 2 <= srat.len() {
+        let (kind, length) = (srat[offset], srat[offset + 1] as usize);
+        if length < 2 || offset + length > srat.len() {
+            klog_warning!("numa: bad SRAT entry at offset {}", offset);
+            break;
+        }
+        f(kind, &srat[offset] as *const u8 as usize);
+        offset += length;
+    }
+}
+
+// Calls a visitor for each memory range attached to a node.
+fn for_each_memory_affinity<F>(mut f: F) where F: FnMut(MemoryRegion) {
+    for_each_srat_entry(|kind, address| {
+        if kind != SRAT_MEMORY {
+            return;
+        }
+        let entry = unsafe { &*(address as *const MemoryAffinity) };
+        let node = entry.domain as usize;
+        if entry.flags & SRAT_ENABLED != 0 && numa::add_node(node) {
+            f(MemoryRegion{
+                address: (entry.base_high as usize) << 32 |
+                    entry.base_low as usize,
+                size: (entry.length_high as usize) << 32 |
+                    entry.length_low as usize,
+                kind: MemoryKind::Available,
+                node: node
+            });
+        }
+    });
+}
+
+// Splits an available region into parts lying on different nodes, memory
+// not described by SRAT is attached to node zero.
+pub fn split_by_node(region: MemoryRegion,
+                     f: &mut FnMut(MemoryRegion) -> Result<(), Error>)
+                     -> Result<(), Error> {
+    if region.kind != MemoryKind::Available {
+        return f(region);
+    }
+
+    let mut address = region.address;
+    while address < region.end() {
+        let (mut containing, mut next) = (None, region.end());
+        for_each_memory_affinity(|affinity| {
+            if affinity.address <= address && address < affinity.end() {
+                containing = Some(affinity);
+            } else if affinity.address > address && affinity.address < next {
+                next = affinity.address;
+            }
+        });
+
+        let part = match containing {
+            Some(affinity) => MemoryRegion{
+                address: address,
+                size: min(region.end(), affinity.end()) - address,
+                node: affinity.node,
+                ..region
+            },
+            None => MemoryRegion{
+                address: address,
+                size: next - address,
+                node: 0,
+                ..region
+            }
+        };
+        try!(f(part));
+        address = part.end();
+    }
+    Ok(())
+}
+
+fn init_cpus() {
+    for_each_srat_entry(|kind, address| {
+        let (apic_id, domain, flags) = unsafe {
+            match kind {
+                SRAT_PROCESSOR => {
+                    let entry = &*(address as *const ProcessorAffinity);
+                    let high = entry.domain_high;
+                    (entry.apic_id as usize,
+                     (high[2] as usize) << 24 | (high[1] as usize) << 16 |
+                     (high[0] as usize) << 8 | entry.domain_low as usize,
+                     entry.flags)
+                },
+                SRAT_X2APIC => {
+                    let entry = &*(address as *const X2ApicAffinity);
+                    (entry.x2apic_id as usize, entry.domain as usize,
+                     entry.flags)
+                },
+                _ => return
+            }
+        };
+        if flags & SRAT_ENABLED != 0 {
+            numa::set_cpu_node(apic_id, domain);
+        }
+    });
+}
+
+fn init_distances() {
+    let slit = match acpi::find_table(b"SLIT") {
+        Some(slit) => slit.data(),
+        None => return
+    };
+    // localities count is followed by a matrix of distances
+    if slit.len() < 8 {
+        return;
+    }
+    let count = slit[..8].iter().rev()
+        .fold(0, |count, &byte| count << 8 | byte as usize);
+    if slit.len() < 8 + count * count {
+        klog_warning!("numa: bad SLIT size");
+        return;
+    }
+    for from in 0..count {
+        for to in 0..count {
+            numa::set_distance(from, to, slit[8 + from * count + to] as usize);
+        }
+    }
+}
+
+// Discovers nodes of memory and CPUs and distances between nodes.
+pub unsafe fn init() {
+    acpi::init();
+    SRAT = acpi::find_table(b"SRAT");
+    if SRAT.is_none() {
+        klog_debug!("numa: no SRAT found, assuming a single node");
+    }
+
+    for_each_memory_affinity(|_| {});
+    init_cpus();
+    init_distances();
+    numa::dump();
+}
@@ -0,0 +1,167 @@
+use arch::multiboot;
+use core::cmp::max;
+use frame::FRAME_SIZE;
+use memory::{self, MemoryKind};
+use paging::{self, Attributes, Cache, PageSize, MMIO, RAM};
+use util::align_up;
+
+// The amount of memory mapped by start.s both at identity and direct map
+// addresses.
+const BOOT_MAPPED_SIZE: usize = 4 * 1024 * 1024 * 1024;
+
+const PTE_PRESENT: u64 = 1 << 0;
+const PTE_WRITE: u64 = 1 << 1;
+const PTE_PWT: u64 = 1 << 3;
+const PTE_PCD: u64 = 1 << 4;
+const PTE_PS: u64 = 1 << 7;
+const PTE_NX: u64 = 1 << 63;
+const PTE_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+const PTE_FLAGS_MASK: u64 = !PTE_ADDRESS_MASK & !PTE_PS;
+
+const CPUID_EXT_FEATURES: u32 = 0x8000_0001;
+const CPUID_EXT_NX: u32 = 1 << 20;
+const CPUID_EXT_PDPE1GB: u32 = 1 << 26;
+
+const CR0_WP: u64 = 1 << 16;
+
+const MSR_EFER: u32 = 0xC000_0080;
+const MSR_EFER_NXE: u32 = 1 << 11;
+
+const LOCAL_APIC_ADDRESS: usize = 0xFEE0_0000;
+const IO_APIC_ADDRESS: usize = 0xFEC0_0000;
+
+// PCI devices are mapped from the end of low memory up to here, the memory
+// map describes only some parts of this hole.
+const PCI_HOLE_END: usize = 4 * 1024 * 1024 * 1024;
+
+// Level of PML4 counted from the bottom.
+pub const ROOT_LEVEL: usize = 3;
+
+static mut TABLES_LIMIT: usize = BOOT_MAPPED_SIZE;
+
+fn attributes_flags(attrs: Attributes) -> u64 {
+    let mut flags = PTE_PRESENT;
+    if attrs.write { flags |= PTE_WRITE; }
+    if !attrs.execute { flags |= PTE_NX; }
+    flags |= match attrs.cache {
+        Cache::WriteBack => 0,
+        Cache::WriteThrough => PTE_PWT,
+        Cache::Uncached | Cache::Device => PTE_PCD | PTE_PWT
+    };
+    flags
+}
+
+fn size_flags(size: PageSize) -> u64 {
+    if size == PageSize::Small { 0 } else { PTE_PS }
+}
+
+pub fn is_valid(entry: u64) -> bool {
+    entry & PTE_PRESENT != 0
+}
+
+// Whether a valid entry at the level maps a page rather than a table.
+pub fn is_page(entry: u64, level: usize) -> bool {
+    level == 0 || (level < ROOT_LEVEL && entry & PTE_PS != 0)
+}
+
+pub fn entry_address(entry: u64) -> usize {
+    (entry & PTE_ADDRESS_MASK) as usize
+}
+
+pub fn table_entry(phys: usize) -> u64 {
+    phys as u64 | PTE_PRESENT | PTE_WRITE
+}
+
+pub fn page_entry(phys: usize, size: PageSize, attrs: Attributes) -> u64 {
+    phys as u64 | attributes_flags(attrs) | size_flags(size)
+}
+
+// Flags of a page entry converted for a page of another size.
+pub fn page_flags(entry: u64, size: PageSize) -> u64 {
+    (entry & PTE_FLAGS_MASK) | size_flags(size)
+}
+
+// Present entries can be changed in place.
+pub unsafe fn replace_entry(entry: *mut u64, virt: usize, value: u64) {
+    *entry = value;
+    invalidate(virt);
+}
+
+// Tables must be allocated from the boot mapping until the new one is set.
+pub fn tables_limit() -> usize {
+    unsafe { TABLES_LIMIT }
+}
+
+fn ext_features() -> u32 {
+    let (_eax, edx): (u32, u32);
+    unsafe {
+        asm!("cpuid" : "={eax}"(_eax), "={edx}"(edx)
+                     : "{eax}"(CPUID_EXT_FEATURES) : "ebx", "ecx");
+    }
+    edx
+}
+
+pub fn huge_pages_supported() -> bool {
+    ext_features() & CPUID_EXT_PDPE1GB != 0
+}
+
+// Makes NX bits and read-only pages effective for the kernel.
+unsafe fn enable_protection() {
+    if ext_features() & CPUID_EXT_NX == 0 {
+        panic!("no-execute pages are not supported by CPU");
+    }
+    asm!("rdmsr; orl $1, %eax; wrmsr"
+         : : "{ecx}"(MSR_EFER), "i"(MSR_EFER_NXE) : "eax", "edx"
+         : "volatile");
+    asm!("movq %cr0, %rax; orq $0, %rax; movq %rax, %cr0"
+         : : "i"(CR0_WP) : "rax" : "volatile");
+}
+
+pub fn invalidate(virt: usize) {
+    unsafe { asm!("invlpg ($0)" : : "r"(virt) : "memory" : "volatile"); }
+}
+
+pub fn invalidate_all() {
+    unsafe {
+        asm!("movq %cr3, %rax; movq %rax, %cr3"
+             : : : "rax", "memory" : "volatile");
+    }
+}
+
+fn pci_hole_start() -> usize {
+    let mut start = 0;
+    for region in memory::memory_map() {
+        if region.kind != MemoryKind::Reserved &&
+            region.end() <= PCI_HOLE_END {
+            start = max(start, region.end());
+        }
+    }
+    align_up(start, FRAME_SIZE)
+}
+
+pub unsafe fn init() {
+    let pml4 = paging::create_root();
+
+    let hole = pci_hole_start();
+    paging::map_direct(hole, PCI_HOLE_END - hole, MMIO);
+    for region in memory::memory_map() {
+        let attrs = match region.kind {
+            MemoryKind::Available | MemoryKind::AcpiReclaimable |
+            MemoryKind::AcpiNvs => RAM,
+            _ => MMIO
+        };
+        paging::map_direct(region.address, region.size, attrs);
+    }
+    paging::map_direct(LOCAL_APIC_ADDRESS, FRAME_SIZE, MMIO);
+    paging::map_direct(IO_APIC_ADDRESS, FRAME_SIZE, MMIO);
+    if let Some(framebuffer) = multiboot::framebuffer_region() {
+        paging::map_direct(framebuffer.address, framebuffer.size, MMIO);
+    }
+    paging::map_kernel();
+
+    enable_protection();
+    asm!("movq $0, %cr3" : : "r"(pml4) : "memory" : "volatile");
+    TABLES_LIMIT = !0;
+
+    klog_debug!("paging: page tables set at 0x{:X}", pml4);
+}
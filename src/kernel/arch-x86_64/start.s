@@ -1,3 +1,10 @@
+	.set MULTIBOOT_MAGIC, 0x1BADB002
+	.set MULTIBOOT_MEMORY_INFO, 1 << 1
+	.set MULTIBOOT_AOUT_KLUDGE, 1 << 16
+	.set MULTIBOOT_FLAGS, MULTIBOOT_MEMORY_INFO | MULTIBOOT_AOUT_KLUDGE
+
+	.set KERNEL_OFFSET, 0xFFFFFFFF80000000
+
 	.set PIC1_DATA_PORT, 0x21
 	.set PIC2_DATA_PORT, 0xA1
 
@@ -23,11 +30,18 @@
 	.set SEGMENT_CODE, 0x8
 	.set SEGMENT_DATA, 0x10
 
+	.set STACK_GUARD_SIZE, 4096
 	.set BOOT_STACK_SIZE, 32 * 1024
+	.set EMERGENCY_STACK_SIZE, 16 * 1024
 
+	.global __boot_stack_guard
 	.global __boot_stack
+	.global __boot_stack_top
+	.global __emergency_stack_guard
+	.global __emergency_stack_top
 	.global __pml4
 	.global __pdp0
+	.global __pdp_high
 	.global __pd0
 	.global __gdt
 	.global __start32
@@ -39,15 +53,38 @@ __pml4:
 	.fill 512, 8
 __pdp0:
 	.fill 512, 8
-__pd0:
+__pdp_high:
 	.fill 512, 8
+__pd0:
+	.fill 4 * 512, 8
 
-	.balign 16
+	/* unmapped once paging is set to catch boot stack overflows */
+__boot_stack_guard:
+	.fill STACK_GUARD_SIZE
 __boot_stack:
 	.fill BOOT_STACK_SIZE
+__boot_stack_top:
 
-	.data
+	/* faults are handled here as the faulting stack can be unusable */
+__emergency_stack_guard:
+	.fill STACK_GUARD_SIZE
+	.fill EMERGENCY_STACK_SIZE
+__emergency_stack_top:
+
+	/* the code below runs at load addresses before paging is set */
+	.section .header, "ax"
 	.balign 4
+multiboot_header:
+	.long MULTIBOOT_MAGIC
+	.long MULTIBOOT_FLAGS
+	.long -(MULTIBOOT_MAGIC + MULTIBOOT_FLAGS)
+	.long multiboot_header
+	.long __kbaddr
+	.long __kdeaddr
+	.long __keaddr
+	.long __start32
+
+	.balign 8
 __gdt:
 	.quad 0
 	.quad GDT_TYPE_CODE | GDT_NONSYS | GDT_PRESENT | GDT_BITS64
@@ -58,7 +95,6 @@ gdti:
 	.word end_of_gdt - __gdt - 1
 	.quad __gdt
 
-	.text
 	.code32
 __start32:
 	/* preserve magic and multiboot_info */
@@ -75,13 +111,35 @@ __start32:
 	orl $(CR4_PAE | CR4_OSFXSR), %edx
 	movl %edx, %cr4
 
-	/* link page table entries and set page map */
-	orl $(PTE_PRESENT | PTE_WRITE), __pml4
-	orl $__pdp0, __pml4
-	orl $(PTE_PRESENT | PTE_WRITE), __pdp0
-	orl $__pd0, __pdp0
-	orl $(PTE_PRESENT | PTE_WRITE | PTE_PS), __pd0
-	movl $__pml4, %eax
+	/* link page table entries mapping load, direct map and link
+	   addresses, the first one is dropped by the final page map */
+	movl $(__pdp0 - KERNEL_OFFSET + PTE_PRESENT + PTE_WRITE), %eax
+	movl %eax, (__pml4 - KERNEL_OFFSET)
+	movl %eax, (__pml4 - KERNEL_OFFSET + 256 * 8)
+	movl $(__pdp_high - KERNEL_OFFSET + PTE_PRESENT + PTE_WRITE), %eax
+	movl %eax, (__pml4 - KERNEL_OFFSET + 511 * 8)
+	movl $(__pd0 - KERNEL_OFFSET + PTE_PRESENT + PTE_WRITE), %eax
+	movl %eax, (__pdp_high - KERNEL_OFFSET + 510 * 8)
+	movl $(__pdp0 - KERNEL_OFFSET), %ecx
+1:
+	movl %eax, (%ecx)
+	addl $4096, %eax
+	addl $8, %ecx
+	cmpl $(__pdp0 - KERNEL_OFFSET + 4 * 8), %ecx
+	jne 1b
+
+	/* map the first 4GiB (firmware tables can be anywhere there) */
+	movl $(__pd0 - KERNEL_OFFSET), %ecx
+	movl $(PTE_PRESENT | PTE_WRITE | PTE_PS), %eax
+2:
+	movl %eax, (%ecx)
+	addl $0x200000, %eax
+	addl $8, %ecx
+	cmpl $(__pd0 - KERNEL_OFFSET + 4 * 4096), %ecx
+	jne 2b
+
+	/* set page map */
+	movl $(__pml4 - KERNEL_OFFSET), %eax
 	movl %eax, %cr3
 
 	/* enable long mode */
@@ -103,11 +161,17 @@ __start32:
 
 	.code64
 start64:
+	/* jump to link addresses */
+	movabsq $start_high, %rax
+	jmp *%rax
+
+	.text
+start_high:
 	/* set segments and stack */
 	movw $SEGMENT_DATA, %ax
 	movw %ax, %ds
 	movw %ax, %ss
-	movq $(__boot_stack + BOOT_STACK_SIZE), %rsp
+	movq $__boot_stack_top, %rsp
 
 	/* extend preserved magic and multiboot_info */
 	shlq $32, %rdi
@@ -115,7 +179,8 @@ start64:
 	shlq $32, %rsi
 	shrq $32, %rsi
 
-	/* call the Rust entry point */
+	/* call the Rust entry point, a zero frame ends backtraces */
+	xorl %ebp, %ebp
 	call __boot
 
 __halt:
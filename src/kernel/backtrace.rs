@@ -0,0 +1,57 @@
+use config::BACKTRACE_DEPTH_MAX;
+use stack;
+use symbols;
+
+// Calls a visitor for each return address of the frame pointer chain
+// (both architectures keep a pair of the previous frame pointer and the
+// return address where it points to). The walk stops on frames lying
+// outside of registered stacks, so a broken chain is never followed.
+pub fn for_each_return_address<F>(frame: usize, mut f: F)
+    where F: FnMut(usize) {
+    let mut frame = frame;
+    for _ in 0..BACKTRACE_DEPTH_MAX {
+        let stack = match stack::find(frame) {
+            Some(stack) => stack,
+            None => break
+        };
+        if frame % 8 != 0 || !stack.contains(frame + 15) {
+            break;
+        }
+        let (next, address) = unsafe {
+            (*(frame as *const usize), *((frame + 8) as *const usize))
+        };
+        if address == 0 {
+            break;
+        }
+        f(address);
+        // frames go up within a stack, but may continue on another one
+        // (e.g. from the emergency stack to the faulting one)
+        if next <= frame && stack.contains(next) {
+            break;
+        }
+        frame = next;
+    }
+}
+
+fn log_address(address: usize, symbol: Option<symbols::Symbol>) {
+    match symbol {
+        Some(symbol) => klog_fatal!("  0x{:X} {}", address, symbol),
+        None => klog_fatal!("  0x{:X}", address)
+    }
+}
+
+// Logs the program counter (if known) and the return addresses.
+pub fn dump(program_counter: Option<usize>, frame: usize) {
+    klog_fatal!("backtrace:");
+    if let Some(address) = program_counter {
+        log_address(address, symbols::symbolize(address));
+    }
+    for_each_return_address(frame, |address| {
+        // the call can be the last instruction of a function
+        let symbol = symbols::symbolize(address - 1).map(|mut symbol| {
+            symbol.offset += 1;
+            symbol
+        });
+        log_address(address, symbol);
+    });
+}
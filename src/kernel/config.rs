@@ -1 +1,14 @@
-pub const MEMORY_REGIONS_MAX: usize = 8;
+pub const FRAME_ORDER_MAX: usize = 10;
+pub const HEAP_GROW_ORDER: usize = 4;
+pub const SLAB_OBJECTS_MIN: usize = 8;
+pub const NUMA_NODES_MAX: usize = 8;
+pub const CPUS_MAX: usize = 64;
+pub const KLOG_BUFFER_SIZE: usize = 64 * 1024;
+pub const KLOG_RECORD_MAX: usize = 512;
+pub const KLOG_SINKS_MAX: usize = 8;
+pub const KLOG_FILTERS_MAX: usize = 16;
+pub const KLOG_MODULE_MAX: usize = 64;
+pub const TRACE_BUFFER_ORDER: usize = 3;
+pub const DMA_POOL_ORDER: usize = 9;
+pub const BACKTRACE_DEPTH_MAX: usize = 32;
+pub const SYMBOL_NAME_MAX: usize = 256;
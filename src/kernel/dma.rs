@@ -0,0 +1,188 @@
+use arch::cpu;
+use config::{DMA_POOL_ORDER, FRAME_ORDER_MAX};
+use frame::{self, FRAME_SIZE};
+use memory;
+use memory_stats::{self, Subsystem};
+use paging;
+use spinlock::{SpinLock, SPIN_LOCK_INIT};
+
+pub const NO_LIMIT: usize = !0;
+pub const LIMIT_32BIT: usize = 1 << 32;
+
+const POOL_FRAMES: usize = 1 << DMA_POOL_ORDER;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mapping {
+    // Always consistent for both CPU and device, taken from the pool mapped
+    // uncached unless caches are coherent anyway.
+    Coherent,
+    // Cached, must be synchronized around device accesses.
+    Streaming
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    ToDevice,
+    FromDevice,
+    Bidirectional
+}
+
+#[derive(Debug)]
+pub struct Buffer {
+    pub address: *mut u8,
+    pub bus_address: usize,
+    pub size: usize,
+    order: usize,
+    mapping: Mapping
+}
+
+// Memory of uncached coherent buffers, set aside at boot since remapping
+// RAM later would split block mappings which may be in use meanwhile.
+static POOL_LOCK: SpinLock = SPIN_LOCK_INIT;
+static mut POOL: usize = 0; // zero if there is no pool
+// One bit per frame of the pool, set bit means the frame is used.
+static mut POOL_BITMAP: [u64; POOL_FRAMES / 64] = [0; POOL_FRAMES / 64];
+
+fn uses_pool(mapping: Mapping) -> bool {
+    mapping == Mapping::Coherent && !cpu::DMA_COHERENT
+}
+
+// Called by the arch paging code before translation is enabled, so the
+// pool is mapped uncached while no one can be using its old mapping.
+pub unsafe fn init() {
+    if cpu::DMA_COHERENT {
+        return;
+    }
+    let bytes = FRAME_SIZE << DMA_POOL_ORDER;
+    match frame::alloc_frames_below(DMA_POOL_ORDER, LIMIT_32BIT) {
+        Some(frames) => {
+            let address = memory::phys_to_virt(frames);
+            cpu::flush_dcache(address, bytes);
+            let attrs = paging::Attributes{
+                cache: paging::Cache::Uncached,
+                ..paging::RAM
+            };
+            paging::map_range(address, frames, bytes, attrs);
+            memory_stats::account_allocated(Subsystem::Dma, bytes);
+            POOL = frames;
+        },
+        None => klog_warning!("dma: no memory for coherent buffers")
+    }
+}
+
+unsafe fn is_pool_used(frame: usize) -> bool {
+    POOL_BITMAP[frame / 64] & (1 << (frame % 64)) != 0
+}
+
+unsafe fn set_pool_used(frame: usize, used: bool) {
+    if used {
+        POOL_BITMAP[frame / 64] |= 1 << (frame % 64);
+    } else {
+        POOL_BITMAP[frame / 64] &= !(1 << (frame % 64));
+    }
+}
+
+// Allocates 2^order frames of the pool aligned to their total size.
+fn pool_alloc(order: usize, limit: usize) -> Option<usize> {
+    let _guard = POOL_LOCK.lock();
+    unsafe {
+        if POOL == 0 || order > DMA_POOL_ORDER {
+            return None;
+        }
+        let count = 1 << order;
+        let mut frame = 0;
+        'outer: while frame + count <= POOL_FRAMES {
+            let address = POOL + frame * FRAME_SIZE;
+            if address + count * FRAME_SIZE > limit {
+                return None;
+            }
+            for cur in frame..frame + count {
+                if is_pool_used(cur) {
+                    frame += count;
+                    continue 'outer;
+                }
+            }
+            for cur in frame..frame + count {
+                set_pool_used(cur, true);
+            }
+            return Some(address);
+        }
+        None
+    }
+}
+
+fn pool_free(address: usize, order: usize) {
+    let _guard = POOL_LOCK.lock();
+    unsafe {
+        let frame = (address - POOL) / FRAME_SIZE;
+        for cur in frame..frame + (1 << order) {
+            assert!(is_pool_used(cur), "freeing DMA buffer which is not used");
+            set_pool_used(cur, false);
+        }
+    }
+}
+
+// Returns a physically contiguous buffer aligned to the given boundary
+// and ending not higher than limit.
+pub fn allocate(size: usize, align: usize, limit: usize,
+                mapping: Mapping) -> Option<Buffer> {
+    let mut order = 0;
+    while FRAME_SIZE << order < size || FRAME_SIZE << order < align {
+        order += 1;
+        if order > FRAME_ORDER_MAX {
+            return None;
+        }
+    }
+
+    let frames = if uses_pool(mapping) {
+        pool_alloc(order, limit)
+    } else {
+        frame::alloc_frames_below(order, limit)
+    };
+    frames.map(|frames| {
+        if !uses_pool(mapping) {
+            memory_stats::account_allocated(Subsystem::Dma,
+                                            FRAME_SIZE << order);
+        }
+
+        // no IOMMU, so devices see physical addresses
+        Buffer{
+            address: memory::phys_to_virt(frames) as *mut u8,
+            bus_address: frames,
+            size: size,
+            order: order,
+            mapping: mapping
+        }
+    })
+}
+
+pub fn free(buffer: Buffer) {
+    if uses_pool(buffer.mapping) {
+        pool_free(buffer.bus_address, buffer.order);
+    } else {
+        frame::free_frames(buffer.bus_address, buffer.order);
+        memory_stats::account_freed(Subsystem::Dma,
+                                    FRAME_SIZE << buffer.order);
+    }
+}
+
+impl Buffer {
+    // Must be called before the device accesses a streaming buffer.
+    pub fn sync_for_device(&self, direction: Direction) {
+        if self.mapping == Mapping::Streaming {
+            let address = self.address as usize;
+            match direction {
+                Direction::ToDevice => cpu::clean_dcache(address, self.size),
+                _ => cpu::flush_dcache(address, self.size)
+            }
+        }
+    }
+
+    // Must be called before the CPU accesses a streaming buffer.
+    pub fn sync_for_cpu(&self, direction: Direction) {
+        if self.mapping == Mapping::Streaming &&
+            direction != Direction::ToDevice {
+            cpu::invalidate_dcache(self.address as usize, self.size);
+        }
+    }
+}
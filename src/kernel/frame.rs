@@ -0,0 +1,230 @@
+use config::FRAME_ORDER_MAX;
+use core::cmp::{max, min};
+use core::ptr::write_bytes;
+use memory::{self, MemoryRegion};
+use numa;
+use spinlock::{SpinLock, SPIN_LOCK_INIT};
+use util::{align_down, align_up};
+
+pub const FRAME_SIZE: usize = 4096;
+
+// Protects the bitmap and the counters once the allocator is set up.
+static LOCK: SpinLock = SPIN_LOCK_INIT;
+
+// One bit per frame starting from BASE, set bit means the frame is used.
+static mut BITMAP: *mut u8 = 0 as *mut u8;
+static mut BASE: usize = 0;
+static mut FRAMES: usize = 0;
+
+static mut FREE: usize = 0;
+static mut USED: usize = 0;
+// Frames between available regions, they are marked used but never freed.
+static mut HOLES: usize = 0;
+
+unsafe fn is_used(frame: usize) -> bool {
+    *BITMAP.offset((frame / 8) as isize) & (1 << (frame % 8)) != 0
+}
+
+unsafe fn set_used(frame: usize, used: bool) {
+    let byte = BITMAP.offset((frame / 8) as isize);
+    if used {
+        *byte |= 1 << (frame % 8);
+    } else {
+        *byte &= !(1 << (frame % 8));
+    }
+}
+
+unsafe fn mark_range(address: usize, size: usize, used: bool) {
+    let from = align_up(address, FRAME_SIZE);
+    let to = align_down(address + size, FRAME_SIZE);
+    let mut frame = (from - BASE) / FRAME_SIZE;
+    while frame < (to - BASE) / FRAME_SIZE {
+        if is_used(frame) != used {
+            set_used(frame, used);
+            if used {
+                FREE -= 1;
+                USED += 1;
+            } else {
+                FREE += 1;
+                USED -= 1;
+            }
+        }
+        frame += 1;
+    }
+}
+
+fn find_bitmap_place(available: &[MemoryRegion], size: usize) -> usize {
+    for region in available {
+        // never place the bitmap at zero address
+        let address = align_up(if region.address == 0 { FRAME_SIZE }
+                               else { region.address }, FRAME_SIZE);
+        if address + size <= region.address + region.size {
+            return address;
+        }
+    }
+    panic!("no room for frame bitmap");
+}
+
+pub unsafe fn init(available: &[MemoryRegion]) {
+    if available.is_empty() {
+        panic!("no available memory for frame allocator");
+    }
+
+    let max_block = FRAME_SIZE << FRAME_ORDER_MAX;
+    let first = available.first().unwrap();
+    let last = available.last().unwrap();
+    BASE = align_down(first.address, max_block);
+    FRAMES = (align_up(last.address + last.size, FRAME_SIZE) - BASE) /
+        FRAME_SIZE;
+
+    let bitmap_size = blocks_used!(FRAMES, 8);
+    let bitmap = find_bitmap_place(available, bitmap_size);
+    BITMAP = memory::phys_to_virt(bitmap) as *mut u8;
+    write_bytes(BITMAP, 0xFF, bitmap_size);
+    USED = FRAMES;
+
+    for region in available {
+        mark_range(region.address, region.size, false);
+    }
+    HOLES = USED;
+    USED = 0;
+    mark_range(align_down(bitmap, FRAME_SIZE),
+               align_up(bitmap_size, FRAME_SIZE), true);
+    // keep zero frame from being confused with a null pointer
+    if BASE == 0 {
+        mark_range(0, FRAME_SIZE, true);
+    }
+
+    klog_debug!("frame allocator: {} frames free, {} in holes, bitmap at \
+        0x{:X}", FREE, HOLES, bitmap);
+}
+
+// Allocates frames within the given address range.
+unsafe fn alloc_in(order: usize, from: usize, to: usize) -> Option<usize> {
+    let _guard = LOCK.lock();
+    let count = 1 << order;
+    if count > FREE || to <= BASE {
+        return None;
+    }
+
+    let frames = min(FRAMES, (to - BASE) / FRAME_SIZE);
+    let mut frame = align_up((max(from, BASE) - BASE) / FRAME_SIZE, count);
+    'outer: while frame + count <= frames {
+        let mut cur = frame + count;
+        while cur > frame {
+            cur -= 1;
+            if is_used(cur) {
+                frame = align_up(cur + 1, count);
+                continue 'outer;
+            }
+        }
+
+        for cur in frame..frame + count {
+            set_used(cur, true);
+        }
+        FREE -= count;
+        USED += count;
+        let address = BASE + frame * FRAME_SIZE;
+        trace!("frame_alloc", order, address);
+        return Some(address);
+    }
+    None
+}
+
+// Allocates 2^order contiguous frames aligned to their total size
+// preferring the NUMA node of the calling CPU.
+pub fn alloc_frames(order: usize) -> Option<usize> {
+    alloc_frames_on(order, numa::current_node())
+}
+
+// The same as alloc_frames, but falls back to other nodes in the order
+// of their distance from the given one.
+pub fn alloc_frames_on(order: usize, node: usize) -> Option<usize> {
+    assert!(order <= FRAME_ORDER_MAX, "frame order is too big");
+    let mut tried = 0;
+    let mut next = Some(node);
+    while let Some(cur) = next {
+        for region in memory::available_memory() {
+            if region.node != cur {
+                continue;
+            }
+            let found = unsafe {
+                alloc_in(order, region.address, region.end())
+            };
+            if found.is_some() {
+                return found;
+            }
+        }
+        tried |= 1 << cur;
+        next = numa::nearest(node, tried);
+    }
+    None
+}
+
+// The same as alloc_frames, but the frames end not higher than limit.
+pub fn alloc_frames_below(order: usize, limit: usize) -> Option<usize> {
+    assert!(order <= FRAME_ORDER_MAX, "frame order is too big");
+    unsafe { alloc_in(order, 0, limit) }
+}
+
+// Holes between available regions are marked used too, so frames must be
+// checked to be available before their bits are trusted.
+fn is_available(address: usize, size: usize) -> bool {
+    memory::available_memory().iter().any(|region| {
+        region.address <= address && address + size <= region.end()
+    })
+}
+
+pub fn free_frames(address: usize, order: usize) {
+    assert!(order <= FRAME_ORDER_MAX, "frame order is too big");
+    trace!("frame_free", order, address);
+    let count = 1 << order;
+    assert!(address % (FRAME_SIZE << order) == 0 &&
+        is_available(address, FRAME_SIZE << order),
+        "bad address of frames to free");
+
+    let _guard = LOCK.lock();
+    unsafe {
+        let frame = (address - BASE) / FRAME_SIZE;
+        for cur in frame..frame + count {
+            assert!(is_used(cur), "freeing frame which is already free");
+        }
+        for cur in frame..frame + count {
+            set_used(cur, false);
+        }
+        FREE += count;
+        USED -= count;
+    }
+}
+
+// Counts free frames lying within the given range.
+pub fn free_count_in(address: usize, size: usize) -> usize {
+    let _guard = LOCK.lock();
+    unsafe {
+        let from = max(align_up(address, FRAME_SIZE), BASE);
+        let to = min(align_down(address + size, FRAME_SIZE),
+                     BASE + FRAMES * FRAME_SIZE);
+        let mut count = 0;
+        let mut frame = (from - BASE) / FRAME_SIZE;
+        while BASE + frame * FRAME_SIZE < to {
+            if !is_used(frame) {
+                count += 1;
+            }
+            frame += 1;
+        }
+        count
+    }
+}
+
+pub fn free_count() -> usize {
+    unsafe { FREE }
+}
+
+// Frames allocated including the bitmap, holes are not counted.
+pub fn used_count() -> usize {
+    unsafe { USED }
+}
+
+pub fn hole_count() -> usize {
+    unsafe { HOLES }
+}
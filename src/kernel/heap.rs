@@ -0,0 +1,291 @@
+#[cfg(debug_heap)]
+use arch::cpu;
+use config::{FRAME_ORDER_MAX, HEAP_GROW_ORDER};
+use core::cmp::{max, min};
+use core::mem::{align_of, size_of};
+use core::ptr::copy_nonoverlapping;
+use frame::{self, FRAME_SIZE};
+#[cfg(debug_heap)]
+use heap_debug;
+use memory;
+use memory_stats::{self, Subsystem};
+use slab;
+use spinlock::{SpinLock, SPIN_LOCK_INIT};
+use util::align_up;
+
+// Free memory chunk, the list of them is kept sorted by address.
+struct Hole {
+    size: usize,
+    next: *mut Hole
+}
+
+// Protects the list of holes and the counters below, the debug heap keeps
+// its list of live allocations under it too.
+pub static LOCK: SpinLock = SPIN_LOCK_INIT;
+
+static mut HEAD: *mut Hole = 0 as *mut Hole;
+static mut SIZE: usize = 0;
+static mut ALLOCATED: usize = 0;
+
+fn hole_size(size: usize) -> usize {
+    align_up(max(size, 1), size_of::<Hole>())
+}
+
+fn hole_align(align: usize) -> usize {
+    max(align, max(align_of::<Hole>(), size_of::<Hole>()))
+}
+
+unsafe fn take(size: usize, align: usize) -> *mut u8 {
+    let mut link: *mut *mut Hole = &mut HEAD;
+    while !(*link).is_null() {
+        let hole = *link;
+        let start = hole as usize;
+        let end = start + (*hole).size;
+        let address = align_up(start, align);
+
+        if address + size <= end {
+            let mut next = (*hole).next;
+            if address + size < end {
+                let rest = (address + size) as *mut Hole;
+                (*rest).size = end - address - size;
+                (*rest).next = next;
+                next = rest;
+            }
+            if address > start {
+                (*hole).size = address - start;
+                (*hole).next = next;
+            } else {
+                *link = next;
+            }
+            return address as *mut u8;
+        }
+
+        link = &mut (*hole).next;
+    }
+    0 as *mut u8
+}
+
+unsafe fn insert(address: usize, size: usize) {
+    let mut prev: *mut Hole = 0 as *mut Hole;
+    let mut next = HEAD;
+    while !next.is_null() && (next as usize) < address {
+        prev = next;
+        next = (*next).next;
+    }
+
+    let hole = address as *mut Hole;
+    (*hole).size = size;
+    (*hole).next = next;
+    if !next.is_null() && address + size == next as usize {
+        (*hole).size += (*next).size;
+        (*hole).next = (*next).next;
+    }
+
+    if prev.is_null() {
+        HEAD = hole;
+    } else if prev as usize + (*prev).size == address {
+        (*prev).size += (*hole).size;
+        (*prev).next = (*hole).next;
+    } else {
+        (*prev).next = hole;
+    }
+}
+
+unsafe fn grow(size: usize, align: usize) -> bool {
+    let mut order = HEAP_GROW_ORDER;
+    while FRAME_SIZE << order < size + align {
+        order += 1;
+        if order > FRAME_ORDER_MAX {
+            return false;
+        }
+    }
+
+    let mut frames = frame::alloc_frames(order);
+    if frames.is_none() && slab::shrink_all() > 0 {
+        frames = frame::alloc_frames(order);
+    }
+
+    match frames {
+        Some(address) => {
+            insert(memory::phys_to_virt(address), FRAME_SIZE << order);
+            SIZE += FRAME_SIZE << order;
+            memory_stats::account_allocated(Subsystem::Heap,
+                                            FRAME_SIZE << order);
+            klog_debug!("heap: grown to {}KiB", SIZE / 1024);
+            true
+        },
+        None => false
+    }
+}
+
+// The same as allocate_raw, but LOCK must be held by the caller.
+pub unsafe fn allocate_locked(size: usize, align: usize) -> *mut u8 {
+    let size = hole_size(size);
+    let align = hole_align(align);
+    loop {
+        let ptr = take(size, align);
+        if !ptr.is_null() {
+            ALLOCATED += size;
+            return ptr;
+        }
+        if !grow(size, align) {
+            return ptr;
+        }
+    }
+}
+
+// The same as deallocate_raw, but LOCK must be held by the caller.
+pub unsafe fn deallocate_locked(ptr: *mut u8, size: usize, _align: usize) {
+    let size = hole_size(size);
+    insert(ptr as usize, size);
+    ALLOCATED -= size;
+}
+
+pub fn allocate_raw(size: usize, align: usize) -> *mut u8 {
+    let _guard = LOCK.lock();
+    unsafe { allocate_locked(size, align) }
+}
+
+pub fn deallocate_raw(ptr: *mut u8, size: usize, align: usize) {
+    let _guard = LOCK.lock();
+    unsafe { deallocate_locked(ptr, size, align); }
+}
+
+#[cfg(not(debug_heap))]
+fn usable_size(size: usize) -> usize {
+    hole_size(size)
+}
+
+// Redzones must follow the requested size exactly.
+#[cfg(debug_heap)]
+fn usable_size(size: usize) -> usize {
+    size
+}
+
+// Allocation sites are recorded by the debug heap only, reading the return
+// address relies on frame pointers.
+#[cfg(not(debug_heap))]
+#[inline(always)]
+fn call_site() -> usize {
+    0
+}
+
+#[cfg(debug_heap)]
+#[inline(always)]
+fn call_site() -> usize {
+    cpu::return_address()
+}
+
+#[cfg(not(debug_heap))]
+fn allocate_from(size: usize, align: usize, _site: usize) -> *mut u8 {
+    allocate_raw(size, align)
+}
+
+#[cfg(debug_heap)]
+fn allocate_from(size: usize, align: usize, site: usize) -> *mut u8 {
+    heap_debug::allocate(size, align, site)
+}
+
+#[cfg(not(debug_heap))]
+fn deallocate_from(ptr: *mut u8, size: usize, align: usize, _site: usize) {
+    deallocate_raw(ptr, size, align);
+}
+
+#[cfg(debug_heap)]
+fn deallocate_from(ptr: *mut u8, size: usize, align: usize, site: usize) {
+    heap_debug::deallocate(ptr, size, align, site);
+}
+
+fn reallocate_from(ptr: *mut u8, old_size: usize, size: usize,
+                   align: usize, site: usize) -> *mut u8 {
+    if usable_size(old_size) == usable_size(size) {
+        return ptr;
+    }
+    let new_ptr = allocate_from(size, align, site);
+    if !new_ptr.is_null() {
+        unsafe { copy_nonoverlapping(ptr, new_ptr, min(old_size, size)); }
+        deallocate_from(ptr, old_size, align, site);
+    }
+    new_ptr
+}
+
+#[inline(never)]
+pub fn allocate(size: usize, align: usize) -> *mut u8 {
+    let ptr = allocate_from(size, align, call_site());
+    trace!("heap_alloc", size, ptr);
+    ptr
+}
+
+#[inline(never)]
+pub fn deallocate(ptr: *mut u8, size: usize, align: usize) {
+    trace!("heap_free", size, ptr);
+    deallocate_from(ptr, size, align, call_site());
+}
+
+#[inline(never)]
+pub fn reallocate(ptr: *mut u8, old_size: usize,
+                  size: usize, align: usize) -> *mut u8 {
+    reallocate_from(ptr, old_size, size, align, call_site())
+}
+
+#[cfg(not(debug_heap))]
+fn leak_report() {}
+
+#[cfg(debug_heap)]
+fn leak_report() {
+    heap_debug::leak_report();
+}
+
+pub fn size() -> usize {
+    unsafe { SIZE }
+}
+
+pub fn allocated() -> usize {
+    unsafe { ALLOCATED }
+}
+
+pub fn oom(size: usize, align: usize) -> ! {
+    klog_fatal!("heap: out of memory allocating {} bytes aligned to {} \
+        ({}KiB allocated of {}KiB)", size, align, allocated() / 1024,
+        size() / 1024);
+    leak_report();
+    panic!("out of memory");
+}
+
+// Entry points used by the `alloc` crate.
+
+#[no_mangle]
+pub extern fn __rust_allocate(size: usize, align: usize) -> *mut u8 {
+    let ptr = allocate_from(size, align, call_site());
+    if ptr.is_null() {
+        oom(size, align);
+    }
+    ptr
+}
+
+#[no_mangle]
+pub extern fn __rust_deallocate(ptr: *mut u8, old_size: usize,
+                                align: usize) {
+    deallocate_from(ptr, old_size, align, call_site());
+}
+
+#[no_mangle]
+pub extern fn __rust_reallocate(ptr: *mut u8, old_size: usize,
+                                size: usize, align: usize) -> *mut u8 {
+    let new_ptr = reallocate_from(ptr, old_size, size, align, call_site());
+    if new_ptr.is_null() {
+        oom(size, align);
+    }
+    new_ptr
+}
+
+#[no_mangle]
+pub extern fn __rust_reallocate_inplace(_ptr: *mut u8, old_size: usize,
+                                        size: usize, _align: usize) -> usize {
+    if usable_size(old_size) == usable_size(size) { size } else { old_size }
+}
+
+#[no_mangle]
+pub extern fn __rust_usable_size(size: usize, _align: usize) -> usize {
+    usable_size(size)
+}
@@ -0,0 +1,143 @@
+use core::mem::size_of;
+use core::ptr::write_bytes;
+use heap;
+use util::align_up;
+
+const REDZONE_SIZE: usize = 16;
+const REDZONE_BYTE: u8 = 0xFD;
+const POISON_BYTE: u8 = 0x6B;
+
+const MAGIC_ALLOCATED: usize = 0xA110_CA7E;
+const MAGIC_FREED: usize = 0xF4EE_D0FF;
+
+// Placed before the front redzone of each allocation.
+#[repr(C)]
+struct Header {
+    hole: [usize; 2], // room for the heap free list entry
+    magic: usize,
+    size: usize,
+    align: usize,
+    site: usize,
+    prev: *mut Header,
+    next: *mut Header
+}
+
+static mut LIVE: *mut Header = 0 as *mut Header;
+
+fn front_size(align: usize) -> usize {
+    align_up(size_of::<Header>() + REDZONE_SIZE, align)
+}
+
+fn block_size(size: usize, align: usize) -> usize {
+    front_size(align) + size + REDZONE_SIZE
+}
+
+unsafe fn header(ptr: *mut u8, align: usize) -> *mut Header {
+    ptr.offset(-(front_size(align) as isize)) as *mut Header
+}
+
+unsafe fn check_redzone(from: *const u8, size: usize) -> Option<usize> {
+    for i in 0..size {
+        if *from.offset(i as isize) != REDZONE_BYTE {
+            return Some(from as usize + i);
+        }
+    }
+    None
+}
+
+unsafe fn corruption(what: &str, ptr: *mut u8, header: *mut Header,
+                     site: usize) -> ! {
+    klog_error!("heap: {} of 0x{:X} freed from 0x{:X}", what, ptr as usize,
+        site);
+    if (*header).magic == MAGIC_ALLOCATED || (*header).magic == MAGIC_FREED {
+        klog_error!("heap: 0x{:X} is {} bytes allocated from 0x{:X}",
+            ptr as usize, (*header).size, (*header).site);
+    }
+    panic!("heap corruption");
+}
+
+pub fn allocate(size: usize, align: usize, site: usize) -> *mut u8 {
+    let _guard = heap::LOCK.lock();
+    unsafe {
+        let block = heap::allocate_locked(block_size(size, align), align);
+        if block.is_null() {
+            return block;
+        }
+
+        let front = front_size(align);
+        let ptr = block.offset(front as isize);
+        let header = block as *mut Header;
+        (*header).magic = MAGIC_ALLOCATED;
+        (*header).size = size;
+        (*header).align = align;
+        (*header).site = site;
+        (*header).prev = 0 as *mut Header;
+        (*header).next = LIVE;
+        if !LIVE.is_null() {
+            (*LIVE).prev = header;
+        }
+        LIVE = header;
+
+        let redzone = front - size_of::<Header>();
+        write_bytes(ptr.offset(-(redzone as isize)), REDZONE_BYTE, redzone);
+        write_bytes(ptr.offset(size as isize), REDZONE_BYTE, REDZONE_SIZE);
+        ptr
+    }
+}
+
+pub fn deallocate(ptr: *mut u8, size: usize, align: usize, site: usize) {
+    let _guard = heap::LOCK.lock();
+    unsafe {
+        let header = header(ptr, align);
+        match (*header).magic {
+            MAGIC_ALLOCATED => {},
+            MAGIC_FREED => corruption("double free", ptr, header, site),
+            _ => corruption("free of unknown memory", ptr, header, site)
+        }
+        if (*header).size != size || (*header).align != align {
+            corruption("free with wrong layout", ptr, header, site);
+        }
+
+        let redzone = front_size(align) - size_of::<Header>();
+        let front = ptr.offset(-(redzone as isize));
+        let back = ptr.offset(size as isize);
+        if let Some(at) = check_redzone(front, redzone)
+                .or_else(|| check_redzone(back, REDZONE_SIZE)) {
+            klog_error!("heap: redzone corrupted at 0x{:X}", at);
+            corruption("redzone corruption", ptr, header, site);
+        }
+
+        if (*header).prev.is_null() {
+            LIVE = (*header).next;
+        } else {
+            (*(*header).prev).next = (*header).next;
+        }
+        if !(*header).next.is_null() {
+            (*(*header).next).prev = (*header).prev;
+        }
+
+        (*header).magic = MAGIC_FREED;
+        write_bytes(ptr, POISON_BYTE, size);
+        heap::deallocate_locked(header as *mut u8, block_size(size, align),
+                                align);
+    }
+}
+
+// Lists allocations which are still live, returns the number of bytes.
+pub fn leak_report() -> usize {
+    let (mut count, mut bytes) = (0, 0);
+    let _guard = heap::LOCK.lock();
+    unsafe {
+        let mut header = LIVE;
+        while !header.is_null() {
+            klog_info!("heap: {} bytes at 0x{:X} allocated from 0x{:X}",
+                (*header).size, header as usize + front_size((*header).align),
+                (*header).site);
+            count += 1;
+            bytes += (*header).size;
+            header = (*header).next;
+        }
+    }
+    klog_info!("heap: {} live allocations of {} bytes", count, bytes);
+    bytes
+}
@@ -1,6 +1,14 @@
-use core::fmt;
+use arch::cpu;
+use config::{KLOG_BUFFER_SIZE, KLOG_FILTERS_MAX, KLOG_MODULE_MAX};
+use config::{KLOG_RECORD_MAX, KLOG_SINKS_MAX};
+use core::cmp::min;
+use core::fmt::{self, Write};
+use core::slice::from_raw_parts;
+use core::str::from_utf8_unchecked;
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use spinlock::{SpinLock, SPIN_LOCK_INIT};
 
-#[derive(PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 pub enum Level {
     Debug,
     Info,
@@ -9,44 +17,532 @@ pub enum Level {
     Fatal,
 }
 
-fn write_nothing(_: &str) {}
+impl Level {
+    fn from_u8(value: u8) -> Level {
+        match value {
+            0 => Level::Debug,
+            1 => Level::Info,
+            2 => Level::Warning,
+            3 => Level::Error,
+            _ => Level::Fatal
+        }
+    }
 
-static mut WRITE: fn(&str) = write_nothing;
-static mut LEVEL: Level = Level::Info;
+    // TODO: use `match` on names when compiler will stop
+    // crashing on string comparisons
+    fn from_name(name: &str) -> Option<Level> {
+        let name = name.as_bytes();
+        if name == "debug".as_bytes() {
+            Some(Level::Debug)
+        } else if name == "info".as_bytes() {
+            Some(Level::Info)
+        } else if name == "warning".as_bytes() {
+            Some(Level::Warning)
+        } else if name == "error".as_bytes() {
+            Some(Level::Error)
+        } else if name == "fatal".as_bytes() {
+            Some(Level::Fatal)
+        } else {
+            None
+        }
+    }
 
-pub fn init(write: fn(&str), level: Level) {
-    unsafe {
-        WRITE = write;
-        LEVEL = level;
+    fn prefix(self) -> &'static str {
+        match self {
+            Level::Debug => "d ",
+            Level::Info => "i ",
+            Level::Warning => "W ",
+            Level::Error => "E ",
+            Level::Fatal => "F ",
+        }
+    }
+}
+
+// Destination of records not lower than the given level.
+#[derive(Clone, Copy)]
+struct Sink {
+    write: fn(&str),
+    level: Level,
+    generation: usize
+}
+
+// Slot of the sink and the generation it was registered with, so that an
+// id of a removed sink does not refer to a later one in the same slot.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SinkId {
+    index: usize,
+    generation: usize
+}
+
+static mut SINKS: [Option<Sink>; KLOG_SINKS_MAX] = [None; KLOG_SINKS_MAX];
+static mut GENERATION: usize = 0;
+
+// Parts of the prefix of each record passed to sinks.
+#[derive(Clone, Copy, Debug)]
+pub struct Format {
+    pub timestamp: bool,
+    pub cpu: bool,
+    pub level: bool,
+    pub module: bool
+}
+
+static mut FORMAT: Format = Format{
+    timestamp: true,
+    cpu: true,
+    level: true,
+    module: false
+};
+
+// Minimum level of records from a module and its submodules.
+#[derive(Clone, Copy)]
+struct Filter {
+    module: [u8; KLOG_MODULE_MAX],
+    len: usize,
+    level: Level
+}
+
+impl Filter {
+    fn applies_to(&self, module: &[u8]) -> bool {
+        let prefix = &self.module[..self.len];
+        module.starts_with(prefix) &&
+            (module.len() == prefix.len() ||
+             module[prefix.len()..].starts_with(b"::"))
     }
 }
 
-struct Writer;
+static mut FILTERS: [Option<Filter>; KLOG_FILTERS_MAX] =
+    [None; KLOG_FILTERS_MAX];
+static mut DEFAULT_LEVEL: Level = Level::Debug;
 
-impl fmt::Write for Writer {
+// Records are kept in a ring buffer from the very start, each one is a
+// header followed by the text: text size (2 bytes), level, CPU id,
+// timestamp in CPU ticks (8 bytes, converted when read) and the static
+// module path address and size (8 bytes each).
+const HEADER_SIZE: usize = 28;
+
+static mut BUFFER: [u8; KLOG_BUFFER_SIZE] = [0; KLOG_BUFFER_SIZE];
+static mut HEAD: usize = 0; // offset of the oldest record
+static mut USED: usize = 0;
+static mut FIRST_SEQ: u64 = 0; // sequence number of the oldest record
+static mut NEXT_SEQ: u64 = 0;
+
+// Everything below is protected by this, the lock is not taken by panic.
+static LOCK: SpinLock = SPIN_LOCK_INIT;
+static PANICKING: AtomicBool = ATOMIC_BOOL_INIT;
+
+// Room for the longest prefix of a record text.
+const PREFIX_MAX: usize = KLOG_MODULE_MAX + 48;
+const LINE_MAX: usize = PREFIX_MAX + KLOG_RECORD_MAX + 1;
+
+// Text being formatted, truncated when reaching the limit.
+struct Line {
+    buf: [u8; LINE_MAX],
+    len: usize,
+    limit: usize
+}
+
+impl fmt::Write for Line {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        unsafe { WRITE(s); }
+        let mut size = min(s.len(), self.limit - self.len);
+        while !s.is_char_boundary(size) {
+            size -= 1;
+        }
+        for &byte in &s.as_bytes()[..size] {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        }
         Ok(())
     }
 }
 
-pub fn log(level: Level, args: fmt::Arguments) {
+impl Line {
+    fn new(limit: usize) -> Line {
+        Line{buf: [0; LINE_MAX], len: 0, limit: limit}
+    }
+
+    fn as_str(&self) -> &str {
+        unsafe { from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+unsafe fn ring_byte(offset: usize) -> *mut u8 {
+    &mut BUFFER[offset % KLOG_BUFFER_SIZE]
+}
+
+unsafe fn record_size(offset: usize) -> usize {
+    HEADER_SIZE + read_header(offset, 0, 2) as usize
+}
+
+unsafe fn drop_oldest() {
+    let size = record_size(HEAD);
+    HEAD = (HEAD + size) % KLOG_BUFFER_SIZE;
+    USED -= size;
+    FIRST_SEQ += 1;
+}
+
+unsafe fn read_header(offset: usize, index: usize, size: usize) -> u64 {
+    let mut value = 0;
+    for i in 0..size {
+        value |= (*ring_byte(offset + index + i) as u64) << (8 * i);
+    }
+    value
+}
+
+unsafe fn write_header(offset: usize, index: usize, size: usize,
+                       value: u64) {
+    for i in 0..size {
+        *ring_byte(offset + index + i) = (value >> (8 * i)) as u8;
+    }
+}
+
+// Returns the offset of the record stored.
+unsafe fn store(level: Level, module: &'static str, text: &[u8]) -> usize {
+    let size = HEADER_SIZE + text.len();
+    while KLOG_BUFFER_SIZE - USED < size {
+        drop_oldest();
+    }
+
+    let offset = (HEAD + USED) % KLOG_BUFFER_SIZE;
+    write_header(offset, 0, 2, text.len() as u64);
+    write_header(offset, 2, 1, level as u64);
+    write_header(offset, 3, 1, cpu::id() as u64);
+    write_header(offset, 4, 8, cpu::timestamp());
+    write_header(offset, 12, 8, module.as_ptr() as u64);
+    write_header(offset, 20, 8, module.len() as u64);
+    for (i, byte) in text.iter().enumerate() {
+        *ring_byte(offset + HEADER_SIZE + i) = *byte;
+    }
+    USED += size;
+    NEXT_SEQ += 1;
+    offset
+}
+
+fn ticks_to_microseconds(ticks: u64) -> u64 {
+    let frequency = cpu::timestamp_frequency();
+    if frequency == 0 {
+        return 0;
+    }
+    ticks / frequency * 1_000_000 + ticks % frequency * 1_000_000 / frequency
+}
+
+// Passes the whole line to the sink at once, so that lines written by
+// the panic path without locking are not mixed up within.
+unsafe fn output(sink: &Sink, record: &Record, text: &str) {
+    if record.level < sink.level ||
+        record.level < module_level(record.module) {
+        return;
+    }
+    let mut line = Line::new(LINE_MAX);
+    if FORMAT.timestamp {
+        let _ = write!(line, "[{:5}.{:06}] ", record.timestamp / 1_000_000,
+            record.timestamp % 1_000_000);
+    }
+    if FORMAT.cpu {
+        let _ = write!(line, "{} ", record.cpu);
+    }
+    if FORMAT.level {
+        let _ = line.write_str(record.level.prefix());
+    }
+    if FORMAT.module {
+        let _ = write!(line, "{}: ", record.module);
+    }
+    let _ = line.write_str(text);
+    let _ = line.write_str("\n");
+    (sink.write)(line.as_str());
+}
+
+unsafe fn output_all(record: &Record, text: &str) {
+    for sink in SINKS.iter() {
+        if let Some(ref sink) = *sink {
+            output(sink, record, text);
+        }
+    }
+}
+
+pub fn set_format(format: Format) {
+    let _guard = LOCK.lock();
+    unsafe { FORMAT = format; }
+}
+
+// Adds a sink and replays everything logged so far to it.
+pub fn register(write: fn(&str), level: Level) -> Option<SinkId> {
+    register_from(Reader::oldest(), write, level)
+}
+
+// Adds a sink and replays records starting from the reader to it.
+pub fn register_from(mut reader: Reader, write: fn(&str), level: Level)
+                     -> Option<SinkId> {
+    let _guard = LOCK.lock();
     unsafe {
-        if level < LEVEL {
-            return;
+        let index = match SINKS.iter().position(|s| s.is_none()) {
+            Some(index) => index,
+            None => return None
+        };
+        GENERATION += 1;
+        let sink = Sink{write: write, level: level, generation: GENERATION};
+
+        let mut text = Line::new(KLOG_RECORD_MAX);
+        while let Some(record) = reader.next_locked(&mut text.buf) {
+            text.len = record.size;
+            output(&sink, &record, text.as_str());
         }
 
-        let writer: &mut fmt::Write = &mut Writer;
-        let prefix = match level {
-            Level::Debug => "d ",
-            Level::Info => "i ",
-            Level::Warning => "W ",
-            Level::Error => "E ",
-            Level::Fatal => "F ",
+        SINKS[index] = Some(sink);
+        Some(SinkId{index: index, generation: GENERATION})
+    }
+}
+
+unsafe fn is_registered(id: SinkId) -> bool {
+    match SINKS[id.index] {
+        Some(ref sink) => sink.generation == id.generation,
+        None => false
+    }
+}
+
+pub fn unregister(id: SinkId) {
+    let _guard = LOCK.lock();
+    unsafe {
+        assert!(is_registered(id), "unregistering unknown klog sink");
+        SINKS[id.index] = None;
+    }
+}
+
+pub fn set_level(id: SinkId, level: Level) {
+    let _guard = LOCK.lock();
+    unsafe {
+        assert!(is_registered(id), "setting level of unknown klog sink");
+        if let Some(ref mut sink) = SINKS[id.index] {
+            sink.level = level;
+        }
+    }
+}
+
+// Module paths are matched without the crate name.
+fn strip_crate(module: &'static str) -> &'static str {
+    match module.find("::") {
+        Some(index) => &module[index + 2..],
+        None => ""
+    }
+}
+
+// Level set by the filter of the longest matching module path. Filters
+// apply to output to sinks only, the buffer keeps all records.
+unsafe fn module_level(module: &str) -> Level {
+    let module = module.as_bytes();
+    let mut matched: Option<&Filter> = None;
+    for filter in FILTERS.iter() {
+        if let Some(ref filter) = *filter {
+            if filter.applies_to(module) &&
+                matched.map_or(true, |m| m.len < filter.len) {
+                matched = Some(filter);
+            }
+        }
+    }
+    matched.map_or(DEFAULT_LEVEL, |filter| filter.level)
+}
+
+// Sets the level for a module (e.g. "arch::device_tree") and its
+// submodules, returns false if there is no room for the filter.
+pub fn set_filter(module: &str, level: Level) -> bool {
+    if module.len() > KLOG_MODULE_MAX {
+        return false;
+    }
+    let _guard = LOCK.lock();
+    unsafe {
+        let index = FILTERS.iter().position(|filter| match *filter {
+            Some(ref filter) => &filter.module[..filter.len] ==
+                module.as_bytes(),
+            None => false
+        }).or_else(|| FILTERS.iter().position(|filter| filter.is_none()));
+        match index {
+            Some(index) => {
+                let mut filter = Filter{
+                    module: [0; KLOG_MODULE_MAX],
+                    len: module.len(),
+                    level: level
+                };
+                for (i, byte) in module.bytes().enumerate() {
+                    filter.module[i] = byte;
+                }
+                FILTERS[index] = Some(filter);
+                true
+            },
+            None => false
+        }
+    }
+}
+
+pub fn remove_filter(module: &str) {
+    let _guard = LOCK.lock();
+    unsafe {
+        for filter in FILTERS.iter_mut() {
+            let found = match *filter {
+                Some(ref cur) => &cur.module[..cur.len] == module.as_bytes(),
+                None => false
+            };
+            if found {
+                *filter = None;
+            }
+        }
+    }
+}
+
+// Level for modules not matching any filter.
+pub fn set_default_level(level: Level) {
+    let _guard = LOCK.lock();
+    unsafe { DEFAULT_LEVEL = level; }
+}
+
+// Applies filters given by the klog command line parameter, e.g.
+// klog=info,arch::device_tree=debug (a level without a module sets the
+// default one).
+pub fn configure(command_line: &str) {
+    for param in command_line.split(' ') {
+        if !param.starts_with("klog=") {
+            continue;
+        }
+        for item in param["klog=".len()..].split(',') {
+            let mut parts = item.splitn(2, '=');
+            let (first, second) = (parts.next().unwrap(), parts.next());
+            let level = Level::from_name(second.unwrap_or(first));
+            match (level, second) {
+                (Some(level), Some(_)) => {
+                    if !set_filter(first, level) {
+                        klog_warning!("klog: no room for filter {}", item);
+                    }
+                },
+                (Some(level), None) => set_default_level(level),
+                (None, _) => klog_warning!("klog: bad filter {}", item)
+            }
+        }
+    }
+}
+
+// Can be called from any CPU and from interrupt handlers, formatting
+// is done without holding the lock as it may log too.
+pub fn log(level: Level, module: &'static str, args: fmt::Arguments) {
+    let module = strip_crate(module);
+    let panicking = PANICKING.load(Ordering::SeqCst);
+    let mut text = Line::new(KLOG_RECORD_MAX);
+    let _ = text.write_fmt(args);
+    if panicking {
+        log_unlocked(level, module, &text);
+        return;
+    }
+
+    let _guard = LOCK.lock();
+    unsafe {
+        let offset = store(level, module, &text.buf[..text.len]);
+        let record = Reader{seq: NEXT_SEQ - 1, offset: offset}.header();
+        output_all(&record, text.as_str());
+    }
+}
+
+// Stores the record only if the lock is free, outputs it anyway.
+fn log_unlocked(level: Level, module: &'static str, text: &Line) {
+    unsafe {
+        let record = match LOCK.try_lock() {
+            Some(_guard) => {
+                let offset = store(level, module, &text.buf[..text.len]);
+                Reader{seq: NEXT_SEQ - 1, offset: offset}.header()
+            },
+            None => Record{
+                seq: NEXT_SEQ,
+                level: level,
+                module: module,
+                cpu: cpu::id(),
+                timestamp: ticks_to_microseconds(cpu::timestamp()),
+                size: text.len
+            }
         };
+        output_all(&record, text.as_str());
+    }
+}
+
+// Logs a fatal record bypassing the lock (which can be held by a stuck
+// CPU or by the panicking one itself), so that it always gets out. Any
+// records logged afterwards bypass the lock as well.
+pub fn log_panic(module: &'static str, args: fmt::Arguments) {
+    PANICKING.store(true, Ordering::SeqCst);
+    log(Level::Fatal, module, args);
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Record {
+    // Records lost due to the buffer wrap show up as gaps in numbers.
+    pub seq: u64,
+    pub level: Level,
+    // Module path without the crate name.
+    pub module: &'static str,
+    pub cpu: usize,
+    // Microseconds since the CPU timestamp counter was started.
+    pub timestamp: u64,
+    // Size of the text copied.
+    pub size: usize
+}
+
+// Cursor reading records in the order they were logged.
+pub struct Reader {
+    seq: u64,
+    offset: usize
+}
+
+impl Reader {
+    // Record at the cursor, its size is the one of the whole text.
+    unsafe fn header(&self) -> Record {
+        Record{
+            seq: self.seq,
+            level: Level::from_u8(read_header(self.offset, 2, 1) as u8),
+            module: from_utf8_unchecked(from_raw_parts(
+                read_header(self.offset, 12, 8) as usize as *const u8,
+                read_header(self.offset, 20, 8) as usize)),
+            cpu: read_header(self.offset, 3, 1) as usize,
+            timestamp: ticks_to_microseconds(read_header(self.offset, 4, 8)),
+            size: record_size(self.offset) - HEADER_SIZE
+        }
+    }
+
+    unsafe fn oldest_locked() -> Reader {
+        Reader{seq: FIRST_SEQ, offset: HEAD}
+    }
+
+    // Starts from the oldest record still in the buffer.
+    pub fn oldest() -> Reader {
+        let _guard = LOCK.lock();
+        unsafe { Reader::oldest_locked() }
+    }
+
+    // Skips all records logged so far.
+    pub fn newest() -> Reader {
+        let _guard = LOCK.lock();
+        unsafe {
+            Reader{seq: NEXT_SEQ, offset: (HEAD + USED) % KLOG_BUFFER_SIZE}
+        }
+    }
+
+    // Copies text of the next record to buf truncating it if needed.
+    pub fn next(&mut self, buf: &mut [u8]) -> Option<Record> {
+        let _guard = LOCK.lock();
+        unsafe { self.next_locked(buf) }
+    }
+
+    unsafe fn next_locked(&mut self, buf: &mut [u8]) -> Option<Record> {
+        if self.seq < FIRST_SEQ {
+            *self = Reader::oldest_locked();
+        }
+        if self.seq == NEXT_SEQ {
+            return None;
+        }
+
+        let mut record = self.header();
+        record.size = min(record.size, buf.len());
+        for i in 0..record.size {
+            buf[i] = *ring_byte(self.offset + HEADER_SIZE + i);
+        }
 
-        writer.write_str(prefix).unwrap();
-        writer.write_fmt(args).unwrap();
-        writer.write_str("\n").unwrap();
+        self.seq += 1;
+        self.offset = (self.offset + record_size(self.offset)) %
+            KLOG_BUFFER_SIZE;
+        Some(record)
     }
 }
@@ -1,7 +1,11 @@
 #![crate_name = "kernel"]
-#![feature(asm, lang_items, linkage, core_intrinsics)]
+#![feature(alloc, asm, collections, lang_items, linkage, core_intrinsics)]
 #![no_std]
 
+// Allocations of these are served by heap.
+extern crate alloc;
+extern crate collections;
+
 #[macro_use]
 pub mod macros;
 
@@ -12,18 +16,52 @@ pub mod arch;
 #[path = "arch-aarch64/mod.rs"]
 pub mod arch;
 
+pub mod backtrace;
 pub mod config;
+pub mod dma;
+pub mod frame;
+pub mod heap;
+#[cfg(debug_heap)]
+pub mod heap_debug;
 pub mod klog;
 pub mod libc;
 pub mod memory;
+pub mod memory_stats;
+pub mod numa;
+pub mod paging;
+pub mod panic_log;
+pub mod slab;
+pub mod spinlock;
+pub mod stack;
+pub mod symbols;
+pub mod trace;
 pub mod util;
 
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+// Set by the first panic, panics while reporting it just halt.
+static PANICKED: AtomicBool = ATOMIC_BOOL_INIT;
+
 #[no_mangle]
 #[lang = "panic_fmt"]
 pub extern fn panic_fmt(args: core::fmt::Arguments,
                         file: &'static str, line: usize) -> ! {
-    klog::log(klog::Level::Fatal,
+    if PANICKED.swap(true, Ordering::SeqCst) {
+        arch::cpu::halt();
+    }
+    klog::log_panic(module_path!(),
         format_args!("panic: {} ({}:{})", args, file, line));
-    extern { fn __halt() -> !; }
-    unsafe { __halt(); }
+    match arch::exception::fault_registers() {
+        Some(registers) => {
+            registers.dump();
+            backtrace::dump(Some(registers.program_counter()),
+                            registers.frame_address());
+        },
+        None => backtrace::dump(None, arch::cpu::frame_address())
+    }
+    panic_log::seal();
+    if trace::is_enabled() {
+        trace::dump();
+    }
+    arch::cpu::halt();
 }
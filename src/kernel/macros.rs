@@ -1,35 +1,68 @@
 macro_rules! klog_debug {
     ($($arg:tt)*) => ({
         use klog;
-        klog::log(klog::Level::Debug, format_args!($($arg)*));
+        klog::log(klog::Level::Debug, module_path!(),
+            format_args!($($arg)*));
     })
 }
 
 macro_rules! klog_info {
     ($($arg:tt)*) => ({
         use klog;
-        klog::log(klog::Level::Info, format_args!($($arg)*));
+        klog::log(klog::Level::Info, module_path!(),
+            format_args!($($arg)*));
     })
 }
 
 macro_rules! klog_warning {
     ($($arg:tt)*) => ({
         use klog;
-        klog::log(klog::Level::Warning, format_args!($($arg)*));
+        klog::log(klog::Level::Warning, module_path!(),
+            format_args!($($arg)*));
     })
 }
 
 macro_rules! klog_error {
     ($($arg:tt)*) => ({
         use klog;
-        klog::log(klog::Level::Error, format_args!($($arg)*));
+        klog::log(klog::Level::Error, module_path!(),
+            format_args!($($arg)*));
     })
 }
 
 macro_rules! klog_fatal {
     ($($arg:tt)*) => ({
         use klog;
-        klog::log(klog::Level::Fatal, format_args!($($arg)*));
+        klog::log(klog::Level::Fatal, module_path!(),
+            format_args!($($arg)*));
+    })
+}
+
+// Records an event with up to two integer arguments if the tracepoint is
+// enabled, costs a single check otherwise.
+macro_rules! trace {
+    ($name:expr) => (trace_point!($name, ["", ""], 0, 0));
+    ($name:expr, $arg0:expr) => (
+        trace_point!($name, [stringify!($arg0), ""], $arg0, 0));
+    ($name:expr, $arg0:expr, $arg1:expr) => (
+        trace_point!($name, [stringify!($arg0), stringify!($arg1)],
+                     $arg0, $arg1));
+}
+
+macro_rules! trace_point {
+    ($name:expr, $args:expr, $arg0:expr, $arg1:expr) => ({
+        use core::sync::atomic::Ordering;
+        use trace;
+        #[link_section = ".tracepoints"]
+        static POINT: trace::Tracepoint = trace::Tracepoint{
+            name: $name,
+            module: module_path!(),
+            args: $args,
+            enabled: trace::DISABLED
+        };
+        if POINT.enabled.load(Ordering::Relaxed) {
+            trace::record(&POINT, $arg0 as u64, $arg1 as u64);
+        }
     })
 }
 
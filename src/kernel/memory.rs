@@ -1,17 +1,329 @@
-use config::MEMORY_REGIONS_MAX;
+use arch::memory::DIRECT_MAP_OFFSET;
+use core::cmp::{max, min};
+use core::mem::size_of;
+use core::slice::{from_raw_parts, from_raw_parts_mut};
+use frame::{self, FRAME_SIZE};
+use util::{align_down, align_up};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryKind {
+    Available,
+    Reserved,
+    AcpiReclaimable,
+    AcpiNvs,
+    Bad,
+    NoMap // reserved and not to be mapped as firmware may use it
+}
 
 #[derive(Clone, Copy, Debug)]
 pub struct MemoryRegion {
     pub address: usize,
-    pub size: usize
+    pub size: usize,
+    pub kind: MemoryKind,
+    pub node: usize // NUMA node, zero if unknown
+}
+
+impl MemoryRegion {
+    pub fn end(&self) -> usize { self.address + self.size }
+
+    pub fn overlaps(&self, other: &MemoryRegion) -> bool {
+        self.address < other.end() && other.address < self.end()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Error {
+    TooManyRegions,
+    NoRoomForRegions
+}
+
+// Calls a visitor for each region of some sort, stops on visitor error.
+pub type ForEachRegion =
+    fn(&mut FnMut(MemoryRegion) -> Result<(), Error>) -> Result<(), Error>;
+
+pub struct RegionSet {
+    buf: *mut MemoryRegion,
+    capacity: usize,
+    len: usize
+}
+
+impl RegionSet {
+    pub unsafe fn from_raw(buf: *mut MemoryRegion,
+                           capacity: usize) -> RegionSet {
+        RegionSet{buf:buf, capacity:capacity, len:0}
+    }
+
+    pub fn regions(&self) -> &[MemoryRegion] {
+        unsafe { from_raw_parts(self.buf, self.len) }
+    }
+
+    fn regions_mut(&mut self) -> &mut [MemoryRegion] {
+        unsafe { from_raw_parts_mut(self.buf, self.len) }
+    }
+
+    // Gives up the set leaving its storage reserved forever.
+    pub fn into_regions(self) -> &'static [MemoryRegion] {
+        unsafe { from_raw_parts(self.buf, self.len) }
+    }
+
+    fn insert(&mut self, index: usize,
+              region: MemoryRegion) -> Result<(), Error> {
+        if self.len == self.capacity {
+            return Err(Error::TooManyRegions);
+        }
+        self.len += 1;
+        let buf = self.regions_mut();
+        let mut i = buf.len() - 1;
+        while i > index {
+            buf[i] = buf[i-1];
+            i -= 1;
+        }
+        buf[index] = region;
+        Ok(())
+    }
+
+    fn remove(&mut self, index: usize) {
+        {
+            let buf = self.regions_mut();
+            for i in index..buf.len()-1 {
+                buf[i] = buf[i+1];
+            }
+        }
+        self.len -= 1;
+    }
+
+    pub fn add(&mut self, region: MemoryRegion) -> Result<(), Error> {
+        if region.size == 0 {
+            return Ok(());
+        }
+        let len = self.len;
+        self.insert(len, region)
+    }
+
+    // Replaces whatever is covered by the region with the region itself.
+    pub fn overlay(&mut self, region: MemoryRegion) -> Result<(), Error> {
+        try!(self.subtract(region));
+        let index = self.regions().iter()
+            .position(|cur| cur.address > region.address)
+            .unwrap_or(self.len);
+        self.insert(index, region)
+    }
+
+    pub fn sort(&mut self) {
+        let buf = self.regions_mut();
+        for i in 1..buf.len() {
+            let mut j = i;
+            while j > 0 && buf[j-1].address > buf[j].address {
+                buf.swap(j-1, j);
+                j -= 1;
+            }
+        }
+    }
+
+    // Sorts regions and joins overlapping or adjacent ones of the same kind
+    // and NUMA node.
+    pub fn merge(&mut self) {
+        self.sort();
+        let mut i = 1;
+        while i < self.len {
+            let prev = self.regions()[i-1];
+            let cur = self.regions()[i];
+            if cur.address <= prev.end() && cur.kind == prev.kind &&
+                cur.node == prev.node {
+                let end = max(prev.end(), cur.end());
+                self.regions_mut()[i-1].size = end - prev.address;
+                self.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    pub fn subtract(&mut self, region: MemoryRegion) -> Result<(), Error> {
+        let mut i = 0;
+        while i < self.len {
+            let cur = self.regions()[i];
+            if region.size == 0 || !cur.overlaps(&region) {
+                i += 1;
+                continue;
+            }
+
+            let head = MemoryRegion{
+                address: cur.address,
+                size: max(region.address, cur.address) - cur.address,
+                kind: cur.kind,
+                node: cur.node
+            };
+            let tail_address = min(region.end(), cur.end());
+            let tail = MemoryRegion{
+                address: tail_address,
+                size: cur.end() - tail_address,
+                kind: cur.kind,
+                node: cur.node
+            };
+
+            self.remove(i);
+            if tail.size != 0 {
+                try!(self.insert(i, tail));
+            }
+            if head.size != 0 {
+                try!(self.insert(i, head));
+                i += 1;
+            }
+            if tail.size != 0 {
+                i += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // Shrinks regions to page boundaries dropping ones becoming empty.
+    pub fn align(&mut self, page_size: usize) {
+        let mut i = 0;
+        while i < self.len {
+            let cur = self.regions()[i];
+            let address = align_up(cur.address, page_size);
+            let end = align_down(cur.end(), page_size);
+            if address < end {
+                let region = &mut self.regions_mut()[i];
+                region.address = address;
+                region.size = end - address;
+                i += 1;
+            } else {
+                self.remove(i);
+            }
+        }
+    }
+}
+
+// Firmware memory map along with available memory derived from it.
+pub struct MemoryMap {
+    pub regions: RegionSet,
+    pub available: RegionSet
+}
+
+impl MemoryMap {
+    // Creates a map of firmware regions of all kinds (the ones which are not
+    // available take precedence) and a set of available regions minus
+    // reserved ones. The storage is taken from the first place of available
+    // memory not reserved, extra is the number of regions to be reserved
+    // later by calling reserve.
+    pub unsafe fn bootstrap(regions: ForEachRegion, reserved: ForEachRegion,
+                            extra: usize) -> Result<MemoryMap, Error> {
+        let (mut regions_count, mut reserved_count) = (0, 0);
+        try!(regions(&mut |_| { regions_count += 1; Ok(()) }));
+        try!(reserved(&mut |_| { reserved_count += 1; Ok(()) }));
+
+        // overlay adds two regions at most, subtraction adds one
+        let map_capacity = 3 * regions_count + 2 * extra;
+        let capacity = 2 * map_capacity + reserved_count + extra + 1;
+        let size = align_up(capacity * size_of::<MemoryRegion>(), FRAME_SIZE);
+        let storage = try!(find_unreserved(regions, reserved, size));
+        let buf = phys_to_virt(storage) as *mut MemoryRegion;
+
+        let mut map = MemoryMap{
+            regions: RegionSet::from_raw(buf, map_capacity),
+            available: RegionSet::from_raw(
+                buf.offset(map_capacity as isize), capacity - map_capacity)
+        };
+
+        try!(regions(&mut |region| {
+            if region.kind == MemoryKind::Available {
+                map.regions.add(region)
+            } else {
+                Ok(())
+            }
+        }));
+        map.regions.merge();
+        try!(regions(&mut |region| {
+            if region.kind != MemoryKind::Available {
+                map.regions.overlay(region)
+            } else {
+                Ok(())
+            }
+        }));
+        map.regions.merge();
+
+        for region in map.regions.regions() {
+            if region.kind == MemoryKind::Available {
+                try!(map.available.add(*region));
+            }
+        }
+        try!(reserved(&mut |region| map.available.subtract(region)));
+        try!(map.available.subtract(MemoryRegion{
+            address: storage,
+            size: size,
+            kind: MemoryKind::Reserved,
+            node: 0
+        }));
+        Ok(map)
+    }
+
+    pub fn reserve(&mut self, region: MemoryRegion) -> Result<(), Error> {
+        try!(self.regions.overlay(region));
+        self.available.subtract(region)
+    }
 }
 
-static mut AVAILABLE_BUF: [MemoryRegion; MEMORY_REGIONS_MAX] =
-    [MemoryRegion{address:0, size:0}; MEMORY_REGIONS_MAX];
+fn is_reserved(regions: ForEachRegion, reserved: ForEachRegion,
+               candidate: &MemoryRegion) -> Result<Option<usize>, Error> {
+    let mut end = None;
+    {
+        let mut check = |region: MemoryRegion| {
+            if candidate.overlaps(&region) {
+                end = Some(region.end());
+            }
+            Ok(())
+        };
+        try!(regions(&mut |region| {
+            if region.kind != MemoryKind::Available {
+                check(region)
+            } else {
+                Ok(())
+            }
+        }));
+        try!(reserved(&mut check));
+    }
+    Ok(end)
+}
+
+fn find_unreserved(regions: ForEachRegion, reserved: ForEachRegion,
+                   size: usize) -> Result<usize, Error> {
+    let mut found = None;
+    try!(regions(&mut |region| {
+        if found.is_some() || region.kind != MemoryKind::Available {
+            return Ok(());
+        }
+
+        // never use zero page so that it can't be confused with null
+        let mut address = align_up(max(region.address, FRAME_SIZE),
+                                   FRAME_SIZE);
+        while address + size <= region.end() {
+            let candidate = MemoryRegion{
+                address: address,
+                size: size,
+                kind: MemoryKind::Available,
+                node: 0
+            };
+            match try!(is_reserved(regions, reserved, &candidate)) {
+                Some(end) => address = align_up(end, FRAME_SIZE),
+                None => {
+                    found = Some(address);
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }));
+    found.ok_or(Error::NoRoomForRegions)
+}
+
+static mut MAP: &'static [MemoryRegion] =
+    &[MemoryRegion{address:0, size:0, kind:MemoryKind::Reserved, node:0}; 0];
 static mut AVAILABLE: &'static [MemoryRegion] =
-    &[MemoryRegion{address:0, size:0}; 0];
+    &[MemoryRegion{address:0, size:0, kind:MemoryKind::Available, node:0}; 0];
 
-unsafe fn set_available_memory(available: &[MemoryRegion]) {
+unsafe fn set_available_memory(available: &'static [MemoryRegion]) {
     let mut iter = available.iter();
     if let Some(mut prev) = iter.next() {
         for cur in iter {
@@ -20,27 +332,84 @@ unsafe fn set_available_memory(available: &[MemoryRegion]) {
             prev = cur;
         }
     }
+    AVAILABLE = available;
+}
+
+pub unsafe fn init(map: MemoryMap) {
+    MAP = map.regions.into_regions();
+    for region in MAP {
+        klog_debug!("memory map: {:?} {}KiB from 0x{:X}", region.kind,
+            blocks_used!(region.size, 1024), region.address);
+    }
 
-    for (to, from) in AVAILABLE_BUF.iter_mut().zip(available.iter()) {
-        *to = *from
+    set_available_memory(map.available.into_regions());
+    for region in AVAILABLE {
+        klog_debug!("available memory: {}KiB from 0x{:X} on node {}",
+            region.size/1024, region.address, region.node);
     }
-    AVAILABLE = &AVAILABLE_BUF[..available.len()];
+    frame::init(AVAILABLE);
+}
+
+pub fn memory_map() -> &'static [MemoryRegion] {
+    unsafe { MAP }
+}
+
+pub fn available_memory() -> &'static [MemoryRegion] {
+    unsafe { AVAILABLE }
+}
+
+// Physical memory is accessed through the direct map, so addresses of
+// frames, firmware tables and such are converted before dereferencing.
+pub fn phys_to_virt(address: usize) -> usize {
+    address + DIRECT_MAP_OFFSET
 }
 
-pub unsafe fn init(available: &[MemoryRegion]) {
-    set_available_memory(available);
-    for region in available {
-        klog_debug!("available memory: {}KiB from 0x{:X}",
-            region.size/1024, region.address);
+pub fn virt_to_phys(address: usize) -> usize {
+    address - DIRECT_MAP_OFFSET
+}
+
+fn linker_region(begin: &usize, end: &usize) -> MemoryRegion {
+    let begin = begin as *const usize as usize;
+    let end = end as *const usize as usize;
+    MemoryRegion{
+        address: begin,
+        size: end - begin,
+        kind: MemoryKind::Reserved,
+        node: 0
     }
 }
 
+extern {
+    static __kbaddr: usize;
+    static __ktbaddr: usize;
+    static __kteaddr: usize;
+    static __krbaddr: usize;
+    static __kreaddr: usize;
+    static __kdbaddr: usize;
+    static __keaddr: usize;
+    static __kvoffset: usize;
+}
+
 pub fn kernel_memory_region() -> MemoryRegion {
-    extern {
-        static __kbaddr: usize;
-        static __keaddr: usize;
+    linker_region(&__kbaddr, &__keaddr)
+}
+
+// Difference between link and load addresses of the kernel code and data.
+pub fn kernel_virtual_offset() -> usize {
+    &__kvoffset as *const usize as usize
+}
+
+// Kernel image parts to be mapped with different permissions.
+pub struct KernelSections {
+    pub text: MemoryRegion,
+    pub rodata: MemoryRegion,
+    pub data: MemoryRegion // along with bss
+}
+
+pub fn kernel_sections() -> KernelSections {
+    KernelSections{
+        text: linker_region(&__ktbaddr, &__kteaddr),
+        rodata: linker_region(&__krbaddr, &__kreaddr),
+        data: linker_region(&__kdbaddr, &__keaddr)
     }
-    let kbaddr = &__kbaddr as *const usize as usize;
-    let keaddr = &__keaddr as *const usize as usize;
-    MemoryRegion{address:kbaddr, size:(keaddr-kbaddr)}
 }
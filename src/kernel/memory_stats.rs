@@ -0,0 +1,150 @@
+use core::cmp::{max, min};
+use frame::{self, FRAME_SIZE};
+use memory::{self, MemoryKind, MemoryRegion};
+use numa;
+
+const KINDS: [MemoryKind; 6] = [
+    MemoryKind::Available,
+    MemoryKind::Reserved,
+    MemoryKind::AcpiReclaimable,
+    MemoryKind::AcpiNvs,
+    MemoryKind::Bad,
+    MemoryKind::NoMap
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Subsystem {
+    Heap,
+    Slab,
+    PageTables,
+    Stacks,
+    Dma,
+    Trace
+}
+
+const SUBSYSTEMS: [Subsystem; 6] = [
+    Subsystem::Heap,
+    Subsystem::Slab,
+    Subsystem::PageTables,
+    Subsystem::Stacks,
+    Subsystem::Dma,
+    Subsystem::Trace
+];
+
+static mut USAGE: [usize; 6] = [0; 6];
+
+#[derive(Clone, Copy, Debug)]
+pub struct RegionStats {
+    pub total: usize,
+    pub free: usize,
+    pub reserved: usize
+}
+
+pub fn account_allocated(subsystem: Subsystem, bytes: usize) {
+    unsafe { USAGE[subsystem as usize] += bytes; }
+}
+
+pub fn account_freed(subsystem: Subsystem, bytes: usize) {
+    unsafe {
+        assert!(USAGE[subsystem as usize] >= bytes,
+            "freeing more memory than accounted");
+        USAGE[subsystem as usize] -= bytes;
+    }
+}
+
+pub fn usage(subsystem: Subsystem) -> usize {
+    unsafe { USAGE[subsystem as usize] }
+}
+
+fn overlap(a: &MemoryRegion, b: &MemoryRegion) -> usize {
+    let from = max(a.address, b.address);
+    let to = min(a.end(), b.end());
+    if from < to { to - from } else { 0 }
+}
+
+// Memory of a region given to the frame allocator and free there, the rest
+// is reserved (e.g. by the kernel image or boot modules).
+pub fn region_stats(region: &MemoryRegion) -> RegionStats {
+    let mut managed = 0;
+    let mut free = 0;
+    if region.kind == MemoryKind::Available {
+        for available in memory::available_memory() {
+            let size = overlap(region, available);
+            if size != 0 {
+                managed += size;
+                free += frame::free_count_in(max(region.address,
+                                                 available.address), size) *
+                    FRAME_SIZE;
+            }
+        }
+    }
+    RegionStats{
+        total: region.size,
+        free: free,
+        reserved: region.size - managed
+    }
+}
+
+pub fn kind_stats(kind: MemoryKind) -> RegionStats {
+    let mut stats = RegionStats{total: 0, free: 0, reserved: 0};
+    for region in memory::memory_map() {
+        if region.kind == kind {
+            let cur = region_stats(region);
+            stats.total += cur.total;
+            stats.free += cur.free;
+            stats.reserved += cur.reserved;
+        }
+    }
+    stats
+}
+
+// Statistics of available memory attached to the given NUMA node.
+pub fn node_stats(node: usize) -> RegionStats {
+    let mut stats = RegionStats{total: 0, free: 0, reserved: 0};
+    for region in memory::memory_map() {
+        if region.kind == MemoryKind::Available && region.node == node {
+            let cur = region_stats(region);
+            stats.total += cur.total;
+            stats.free += cur.free;
+            stats.reserved += cur.reserved;
+        }
+    }
+    stats
+}
+
+pub fn total_memory() -> usize {
+    kind_stats(MemoryKind::Available).total
+}
+
+pub fn free_memory() -> usize {
+    frame::free_count() * FRAME_SIZE
+}
+
+pub fn dump() {
+    for region in memory::memory_map() {
+        let stats = region_stats(region);
+        klog_info!("memory: {:?} 0x{:X}-0x{:X}: {}KiB total, {}KiB free, \
+            {}KiB reserved", region.kind, region.address, region.end(),
+            stats.total / 1024, stats.free / 1024, stats.reserved / 1024);
+    }
+    for &kind in &KINDS {
+        let stats = kind_stats(kind);
+        if stats.total != 0 {
+            klog_info!("memory: {:?}: {}KiB total, {}KiB free, \
+                {}KiB reserved", kind, stats.total / 1024,
+                stats.free / 1024, stats.reserved / 1024);
+        }
+    }
+    for node in 0..numa::node_count() {
+        let stats = node_stats(node);
+        klog_info!("memory: node {}: {}KiB total, {}KiB free, \
+            {}KiB reserved", node, stats.total / 1024, stats.free / 1024,
+            stats.reserved / 1024);
+    }
+    for &subsystem in &SUBSYSTEMS {
+        klog_info!("memory: {:?} uses {}KiB", subsystem,
+            usage(subsystem) / 1024);
+    }
+    klog_info!("memory: {}KiB free of {}KiB", free_memory() / 1024,
+        total_memory() / 1024);
+}
@@ -0,0 +1,102 @@
+use arch::cpu;
+use config::{CPUS_MAX, NUMA_NODES_MAX};
+
+// Relative access costs as defined by ACPI SLIT (device trees use the same).
+pub const LOCAL_DISTANCE: usize = 10;
+pub const REMOTE_DISTANCE: usize = 20;
+
+static mut NODES: usize = 1;
+static mut CPU_NODES: [u8; CPUS_MAX] = [0; CPUS_MAX];
+static mut CPUS_KNOWN: [bool; CPUS_MAX] = [false; CPUS_MAX];
+
+// Zero means that a distance is unknown.
+static mut DISTANCES: [[u8; NUMA_NODES_MAX]; NUMA_NODES_MAX] =
+    [[0; NUMA_NODES_MAX]; NUMA_NODES_MAX];
+
+// Makes sure the node is counted, returns false if it can't be handled.
+pub fn add_node(node: usize) -> bool {
+    if node >= NUMA_NODES_MAX {
+        klog_warning!("numa: node {} is ignored (too many nodes)", node);
+        return false;
+    }
+    unsafe {
+        if node >= NODES {
+            NODES = node + 1;
+        }
+    }
+    true
+}
+
+pub fn set_cpu_node(cpu: usize, node: usize) {
+    if cpu >= CPUS_MAX {
+        klog_warning!("numa: cpu {} is ignored (too many cpus)", cpu);
+        return;
+    }
+    if add_node(node) {
+        unsafe {
+            CPU_NODES[cpu] = node as u8;
+            CPUS_KNOWN[cpu] = true;
+        }
+    }
+}
+
+pub fn set_distance(from: usize, to: usize, distance: usize) {
+    if add_node(from) && add_node(to) {
+        unsafe { DISTANCES[from][to] = distance as u8; }
+    }
+}
+
+pub fn node_count() -> usize {
+    unsafe { NODES }
+}
+
+pub fn cpu_node(cpu: usize) -> usize {
+    if cpu < CPUS_MAX {
+        unsafe { CPU_NODES[cpu] as usize }
+    } else {
+        0
+    }
+}
+
+// Node of the CPU running the caller.
+pub fn current_node() -> usize {
+    cpu_node(cpu::id())
+}
+
+pub fn distance(from: usize, to: usize) -> usize {
+    assert!(from < node_count() && to < node_count(), "bad NUMA node");
+    match unsafe { DISTANCES[from][to] } {
+        0 if from == to => LOCAL_DISTANCE,
+        0 => REMOTE_DISTANCE,
+        distance => distance as usize
+    }
+}
+
+// Returns the nearest node to the given one not among tried (a bit mask).
+pub fn nearest(node: usize, tried: usize) -> Option<usize> {
+    let mut nearest = None;
+    for cur in 0..node_count() {
+        if tried & (1 << cur) != 0 {
+            continue;
+        }
+        match nearest {
+            Some(best) if distance(node, best) <= distance(node, cur) => {},
+            _ => nearest = Some(cur)
+        }
+    }
+    nearest
+}
+
+pub fn dump() {
+    for cpu in 0..CPUS_MAX {
+        if unsafe { CPUS_KNOWN[cpu] } {
+            klog_debug!("numa: cpu {} is on node {}", cpu, cpu_node(cpu));
+        }
+    }
+    for from in 0..node_count() {
+        for to in 0..node_count() {
+            klog_debug!("numa: distance from node {} to {} is {}",
+                from, to, distance(from, to));
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use arch::paging::{ROOT_LEVEL, entry_address, huge_pages_supported,
+                   invalidate, invalidate_all, is_page, is_valid, page_entry,
+                   page_flags, replace_entry, table_entry, tables_limit};
+use core::ptr::write_bytes;
+use frame::{self, FRAME_SIZE};
+use memory::{self, MemoryRegion};
+use memory_stats::{self, Subsystem};
+use util::{align_down, align_up};
+
+const TABLE_ENTRIES: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PageSize {
+    Small, // 4KiB
+    Large, // 2MiB
+    Huge   // 1GiB
+}
+
+impl PageSize {
+    pub fn bytes(self) -> usize {
+        match self {
+            PageSize::Small => 4 * 1024,
+            PageSize::Large => 2 * 1024 * 1024,
+            PageSize::Huge => 1024 * 1024 * 1024
+        }
+    }
+
+    // Table level where a page of this size is mapped, counted from the
+    // bottom up to ROOT_LEVEL of the arch.
+    fn level(self) -> usize {
+        match self {
+            PageSize::Small => 0,
+            PageSize::Large => 1,
+            PageSize::Huge => 2
+        }
+    }
+
+    fn from_level(level: usize) -> PageSize {
+        match level {
+            0 => PageSize::Small,
+            1 => PageSize::Large,
+            _ => PageSize::Huge
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cache {
+    WriteBack,
+    WriteThrough,
+    Uncached,
+    Device
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Attributes {
+    pub write: bool,
+    pub execute: bool,
+    pub cache: Cache
+}
+
+pub const RAM: Attributes =
+    Attributes{write: true, execute: false, cache: Cache::WriteBack};
+pub const MMIO: Attributes =
+    Attributes{write: true, execute: false, cache: Cache::Device};
+
+static mut ROOT: *mut u64 = 0 as *mut u64;
+
+fn table_index(virt: usize, level: usize) -> usize {
+    (virt >> (12 + 9 * level)) & (TABLE_ENTRIES - 1)
+}
+
+unsafe fn alloc_table() -> *mut u64 {
+    let table = match frame::alloc_frames_below(0, tables_limit()) {
+        Some(address) => memory::phys_to_virt(address) as *mut u64,
+        None => panic!("no memory for page table")
+    };
+    write_bytes(table, 0, TABLE_ENTRIES);
+    memory_stats::account_allocated(Subsystem::PageTables, FRAME_SIZE);
+    table
+}
+
+// Allocates the empty root table returning its physical address, the arch
+// code switches to it once everything needed is mapped.
+pub unsafe fn create_root() -> usize {
+    ROOT = alloc_table();
+    memory::virt_to_phys(ROOT as usize)
+}
+
+unsafe fn entry_table(entry: u64) -> *mut u64 {
+    memory::phys_to_virt(entry_address(entry)) as *mut u64
+}
+
+fn new_table_entry(table: *mut u64) -> u64 {
+    table_entry(memory::virt_to_phys(table as usize))
+}
+
+// Frees a table with entries at the level along with its subtables.
+unsafe fn free_table(table: *mut u64, level: usize) {
+    for i in 0..TABLE_ENTRIES {
+        let entry = *table.offset(i as isize);
+        if is_valid(entry) && !is_page(entry, level) {
+            free_table(entry_table(entry), level - 1);
+        }
+    }
+    frame::free_frames(memory::virt_to_phys(table as usize), 0);
+    memory_stats::account_freed(Subsystem::PageTables, FRAME_SIZE);
+}
+
+// Replaces a large page entry with a table of smaller pages mapping the
+// same memory.
+unsafe fn split_entry(entry: *mut u64, virt: usize, level: usize) {
+    let table = alloc_table();
+    let size = PageSize::from_level(level - 1);
+    let base = entry_address(*entry);
+    let flags = page_flags(*entry, size);
+    for i in 0..TABLE_ENTRIES {
+        *table.offset(i as isize) = (base + i * size.bytes()) as u64 | flags;
+    }
+    replace_entry(entry, virt, new_table_entry(table));
+}
+
+// Returns the entry for virt at the given level creating tables if needed.
+unsafe fn walk(virt: usize, level: usize) -> *mut u64 {
+    let mut table = ROOT;
+    let mut cur = ROOT_LEVEL;
+    loop {
+        let entry = table.offset(table_index(virt, cur) as isize);
+        if cur == level {
+            return entry;
+        }
+        if !is_valid(*entry) {
+            *entry = new_table_entry(alloc_table());
+        } else if is_page(*entry, cur) {
+            split_entry(entry, virt, cur);
+        }
+        table = entry_table(*entry);
+        cur -= 1;
+    }
+}
+
+// Returns the leaf entry mapping virt with its level.
+unsafe fn find(virt: usize) -> Option<(*mut u64, usize)> {
+    let mut table = ROOT;
+    let mut level = ROOT_LEVEL;
+    loop {
+        let entry = table.offset(table_index(virt, level) as isize);
+        if !is_valid(*entry) {
+            return None;
+        }
+        if is_page(*entry, level) {
+            return Some((entry, level));
+        }
+        table = entry_table(*entry);
+        level -= 1;
+    }
+}
+
+pub unsafe fn map(virt: usize, phys: usize, size: PageSize,
+                  attrs: Attributes) {
+    assert!(virt % size.bytes() == 0 && phys % size.bytes() == 0,
+        "unaligned page mapping");
+    let entry = walk(virt, size.level());
+    if is_valid(*entry) && !is_page(*entry, size.level()) {
+        // translations cached from the table must be gone before it is
+        // freed
+        let table = entry_table(*entry);
+        *entry = 0;
+        invalidate_all();
+        free_table(table, size.level() - 1);
+    }
+    let value = page_entry(phys, size, attrs);
+    if is_valid(*entry) {
+        replace_entry(entry, virt, value);
+    } else {
+        *entry = value;
+        invalidate(virt);
+    }
+}
+
+// Maps a range using the largest pages possible.
+pub unsafe fn map_range(virt: usize, phys: usize, size: usize,
+                        attrs: Attributes) {
+    let huge = huge_pages_supported();
+    let mut offset = 0;
+    while offset < size {
+        let (v, p, left) = (virt + offset, phys + offset, size - offset);
+        let mut page = PageSize::Small;
+        for &candidate in &[PageSize::Huge, PageSize::Large] {
+            let bytes = candidate.bytes();
+            if (candidate != PageSize::Huge || huge) && v % bytes == 0 &&
+                p % bytes == 0 && left >= bytes {
+                page = candidate;
+                break;
+            }
+        }
+        map(v, p, page, attrs);
+        offset += page.bytes();
+    }
+}
+
+// Unmaps a page returning its size (emptied page tables are kept).
+pub unsafe fn unmap(virt: usize) -> Option<PageSize> {
+    find(virt).map(|(entry, level)| {
+        *entry = 0;
+        invalidate(virt);
+        PageSize::from_level(level)
+    })
+}
+
+// Unmaps a range splitting larger pages which are partially in it.
+pub unsafe fn unmap_range(virt: usize, size: usize) {
+    assert!(virt % FRAME_SIZE == 0 && size % FRAME_SIZE == 0,
+        "unaligned range to unmap");
+    let mut offset = 0;
+    while offset < size {
+        let v = virt + offset;
+        match find(v) {
+            Some((entry, level)) => {
+                let bytes = PageSize::from_level(level).bytes();
+                if v % bytes == 0 && size - offset >= bytes {
+                    *entry = 0;
+                    invalidate(v);
+                    offset += bytes;
+                } else {
+                    walk(v, level - 1); // splits the page
+                }
+            },
+            None => offset += FRAME_SIZE
+        }
+    }
+}
+
+// Unmaps a single small page splitting a larger one if needed.
+pub unsafe fn unmap_small(virt: usize) {
+    let entry = walk(virt, PageSize::Small.level());
+    *entry = 0;
+    invalidate(virt);
+}
+
+pub fn translate(virt: usize) -> Option<usize> {
+    unsafe {
+        find(virt).map(|(entry, level)| {
+            let mask = PageSize::from_level(level).bytes() - 1;
+            entry_address(*entry) & !mask | virt & mask
+        })
+    }
+}
+
+// Maps physical memory at its direct map address.
+pub unsafe fn map_direct(address: usize, size: usize, attrs: Attributes) {
+    let from = align_down(address, FRAME_SIZE);
+    let to = align_up(address + size, FRAME_SIZE);
+    map_range(memory::phys_to_virt(from), from, to - from, attrs);
+}
+
+// Maps the kernel image sections with the least permissions needed, the
+// direct map alias of the image is made read-only too (on arches linking
+// the kernel at load addresses the sections are mapped over it).
+pub unsafe fn map_kernel() {
+    let sections = memory::kernel_sections();
+    let image = memory::kernel_memory_region();
+    let read_only = Attributes{write: false, ..RAM};
+    map_direct(image.address, image.size, read_only);
+    map_linked(sections.text, Attributes{execute: true, ..read_only});
+    map_linked(sections.rodata, read_only);
+    map_linked(sections.data, RAM);
+}
+
+// Maps a kernel section at its link address.
+unsafe fn map_linked(section: MemoryRegion, attrs: Attributes) {
+    let from = align_down(section.address, FRAME_SIZE);
+    let to = align_up(section.end(), FRAME_SIZE);
+    map_range(from + memory::kernel_virtual_offset(), from, to - from, attrs);
+}
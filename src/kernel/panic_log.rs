@@ -0,0 +1,138 @@
+use arch::cpu;
+use arch::memory::panic_log_region;
+use config::KLOG_RECORD_MAX;
+use core::str::from_utf8;
+use klog;
+use memory::{self, MemoryRegion};
+
+// Set only when the log is sealed by a panic, so logs of normal reboots
+// are not reported.
+const MAGIC: u64 = 0x474F4C43494E4150; // "PANICLOG"
+
+const FNV_OFFSET: u64 = 0xCBF29CE484222325;
+const FNV_PRIME: u64 = 0x100000001B3;
+
+// Placed at the beginning of the region, the rest is a ring of the last
+// klog output.
+#[repr(C)]
+struct Header {
+    magic: u64,
+    head: u64,
+    used: u64,
+    checksum: u64
+}
+
+const HEADER_SIZE: usize = 32;
+
+// Memory surviving warm reboots provided by the platform and excluded from
+// available memory (see arch memory), set only if it is usable.
+static mut REGION: Option<MemoryRegion> = None;
+static mut ACTIVE: bool = false;
+
+unsafe fn header() -> &'static mut Header {
+    &mut *(memory::phys_to_virt(REGION.unwrap().address) as *mut Header)
+}
+
+unsafe fn data_size() -> usize {
+    REGION.unwrap().size - HEADER_SIZE
+}
+
+unsafe fn data_byte(offset: usize) -> &'static mut u8 {
+    let data = memory::phys_to_virt(REGION.unwrap().address) + HEADER_SIZE;
+    &mut *((data + offset % data_size()) as *mut u8)
+}
+
+unsafe fn checksum() -> u64 {
+    let header = header();
+    let mut hash = FNV_OFFSET;
+    {
+        let mut add = |byte: u8| {
+            hash = (hash ^ byte as u64).wrapping_mul(FNV_PRIME);
+        };
+        for &value in [header.magic, header.head, header.used].iter() {
+            for i in 0..8 {
+                add((value >> (i * 8)) as u8);
+            }
+        }
+        for i in 0..header.used as usize {
+            add(*data_byte(header.head as usize + i));
+        }
+    }
+    hash
+}
+
+unsafe fn is_valid() -> bool {
+    let header = header();
+    let size = data_size() as u64;
+    header.magic == MAGIC && header.head < size && header.used <= size &&
+        header.checksum == checksum()
+}
+
+// Logs the saved output line by line.
+unsafe fn print() {
+    klog_warning!("panic log: output of the previous boot ending in panic:");
+    let header = header();
+    let mut line = [0; KLOG_RECORD_MAX];
+    let mut len = 0;
+    for i in 0..header.used as usize {
+        let byte = *data_byte(header.head as usize + i);
+        if byte != b'\n' && len < line.len() {
+            line[len] = byte;
+            len += 1;
+        }
+        if byte == b'\n' || i + 1 == header.used as usize {
+            klog_warning!("panic log: {}",
+                from_utf8(&line[..len]).unwrap_or("<bad text>"));
+            len = 0;
+        }
+    }
+}
+
+// Sink appending to the ring, the oldest output is overwritten.
+fn write(s: &str) {
+    unsafe {
+        let header = header();
+        let size = data_size() as u64;
+        for &byte in s.as_bytes() {
+            *data_byte((header.head + header.used) as usize) = byte;
+            if header.used < size {
+                header.used += 1;
+            } else {
+                header.head = (header.head + 1) % size;
+            }
+        }
+    }
+}
+
+// Prints the log saved by a panic before the reboot (if any), then clears
+// it and starts saving klog output. Records logged so far are not saved,
+// so the printed log does not get into the new one.
+pub unsafe fn init() {
+    REGION = match panic_log_region() {
+        Some(region) if region.size > HEADER_SIZE => Some(region),
+        _ => {
+            klog_info!("panic log: no memory region for it");
+            return;
+        }
+    };
+    let start = klog::Reader::newest();
+    if is_valid() {
+        print();
+    }
+    *header() = Header{magic: 0, head: 0, used: 0, checksum: 0};
+    ACTIVE = klog::register_from(start, write, klog::Level::Debug).is_some();
+}
+
+// Makes the log to be reported on the next boot, called on panic.
+pub fn seal() {
+    unsafe {
+        if !ACTIVE {
+            return;
+        }
+        let header = header();
+        header.magic = MAGIC;
+        header.checksum = checksum();
+        let region = REGION.unwrap();
+        cpu::clean_dcache(memory::phys_to_virt(region.address), region.size);
+    }
+}
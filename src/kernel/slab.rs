@@ -0,0 +1,270 @@
+use config::{FRAME_ORDER_MAX, SLAB_OBJECTS_MIN};
+use core::cmp::max;
+use core::mem::{align_of, size_of};
+use frame::{self, FRAME_SIZE};
+use heap;
+use memory;
+use memory_stats::{self, Subsystem};
+use spinlock::{SpinLock, SPIN_LOCK_INIT};
+use util::{align_down, align_up};
+
+// Header placed at the beginning of each slab.
+struct Slab {
+    cache: *const Cache,
+    prev: *mut Slab,
+    next: *mut Slab,
+    free: *mut FreeObject,
+    in_use: usize
+}
+
+struct FreeObject {
+    next: *mut FreeObject
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Stats {
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    pub objects_in_use: usize,
+    pub pages: usize
+}
+
+// Objects are constructed once when their slab is created, so they must
+// be returned to the cache in the constructed state.
+pub struct Cache {
+    name: &'static str,
+    next: *mut Cache,
+    object_size: usize,
+    offset: usize,
+    order: usize,
+    objects_per_slab: usize,
+    constructor: Option<fn(*mut u8)>,
+    // Slabs are kept in lists by the number of objects in use, so
+    // allocation takes the first partial or empty slab.
+    empty: *mut Slab,
+    partial: *mut Slab,
+    full: *mut Slab,
+    objects_in_use: usize,
+    slabs_count: usize
+}
+
+// Protects the list of caches along with slab lists and counters of each.
+static LOCK: SpinLock = SPIN_LOCK_INIT;
+static mut CACHES: *mut Cache = 0 as *mut Cache;
+
+pub fn create(name: &'static str, size: usize, align: usize,
+              constructor: Option<fn(*mut u8)>) -> &'static mut Cache {
+    assert!(align <= FRAME_SIZE, "slab object alignment is too big");
+    let align = max(align, align_of::<FreeObject>());
+    let object_size = align_up(max(size, size_of::<FreeObject>()), align);
+    let offset = align_up(size_of::<Slab>(), align);
+
+    let mut order = 0;
+    while (FRAME_SIZE << order) - offset < object_size * SLAB_OBJECTS_MIN &&
+        order < FRAME_ORDER_MAX {
+        order += 1;
+    }
+    assert!((FRAME_SIZE << order) - offset >= object_size,
+        "slab object is too big");
+
+    let ptr = heap::allocate(size_of::<Cache>(), align_of::<Cache>());
+    if ptr.is_null() {
+        heap::oom(size_of::<Cache>(), align_of::<Cache>());
+    }
+
+    let _guard = LOCK.lock();
+    unsafe {
+        let cache = &mut *(ptr as *mut Cache);
+        *cache = Cache{
+            name: name,
+            next: CACHES,
+            object_size: object_size,
+            offset: offset,
+            order: order,
+            objects_per_slab: ((FRAME_SIZE << order) - offset) / object_size,
+            constructor: constructor,
+            empty: 0 as *mut Slab,
+            partial: 0 as *mut Slab,
+            full: 0 as *mut Slab,
+            objects_in_use: 0,
+            slabs_count: 0
+        };
+        CACHES = cache;
+        cache
+    }
+}
+
+unsafe fn push(list: &mut *mut Slab, slab: *mut Slab) {
+    (*slab).prev = 0 as *mut Slab;
+    (*slab).next = *list;
+    if !(*list).is_null() {
+        (**list).prev = slab;
+    }
+    *list = slab;
+}
+
+unsafe fn unlink(list: &mut *mut Slab, slab: *mut Slab) {
+    if (*slab).prev.is_null() {
+        *list = (*slab).next;
+    } else {
+        (*(*slab).prev).next = (*slab).next;
+    }
+    if !(*slab).next.is_null() {
+        (*(*slab).next).prev = (*slab).prev;
+    }
+}
+
+impl Cache {
+    fn slab_size(&self) -> usize {
+        FRAME_SIZE << self.order
+    }
+
+    fn list(&mut self, in_use: usize) -> &mut *mut Slab {
+        if in_use == 0 {
+            &mut self.empty
+        } else if in_use == self.objects_per_slab {
+            &mut self.full
+        } else {
+            &mut self.partial
+        }
+    }
+
+    // Moves the slab to the list matching its objects in use.
+    unsafe fn relink(&mut self, slab: *mut Slab, old_in_use: usize) {
+        unlink(self.list(old_in_use), slab);
+        push(self.list((*slab).in_use), slab);
+    }
+
+    unsafe fn grow(&mut self) -> bool {
+        let address = match frame::alloc_frames(self.order) {
+            Some(address) => memory::phys_to_virt(address),
+            None => return false
+        };
+
+        let slab = address as *mut Slab;
+        (*slab).cache = self;
+        (*slab).free = 0 as *mut FreeObject;
+        (*slab).in_use = 0;
+
+        let mut i = self.objects_per_slab;
+        while i > 0 {
+            i -= 1;
+            let object = address + self.offset + i * self.object_size;
+            if let Some(constructor) = self.constructor {
+                constructor(object as *mut u8);
+            }
+            let free = object as *mut FreeObject;
+            (*free).next = (*slab).free;
+            (*slab).free = free;
+        }
+
+        push(&mut self.empty, slab);
+        self.slabs_count += 1;
+        memory_stats::account_allocated(Subsystem::Slab, self.slab_size());
+        true
+    }
+
+    pub fn alloc(&mut self) -> *mut u8 {
+        let _guard = LOCK.lock();
+        unsafe {
+            if self.partial.is_null() && self.empty.is_null() &&
+                !self.grow() {
+                return 0 as *mut u8;
+            }
+            let slab = if self.partial.is_null() {
+                self.empty
+            } else {
+                self.partial
+            };
+
+            let free = (*slab).free;
+            (*slab).free = (*free).next;
+            (*slab).in_use += 1;
+            self.relink(slab, (*slab).in_use - 1);
+            self.objects_in_use += 1;
+            free as *mut u8
+        }
+    }
+
+    pub fn free(&mut self, ptr: *mut u8) {
+        let slab = align_down(ptr as usize, self.slab_size()) as *mut Slab;
+        let _guard = LOCK.lock();
+        unsafe {
+            assert!((*slab).cache == self as *const Cache,
+                "freeing object of another cache");
+            assert!((*slab).in_use > 0, "freeing object of empty slab");
+            let free = ptr as *mut FreeObject;
+            (*free).next = (*slab).free;
+            (*slab).free = free;
+            (*slab).in_use -= 1;
+            self.relink(slab, (*slab).in_use + 1);
+            self.objects_in_use -= 1;
+        }
+    }
+
+    // Releases slabs having no objects in use, returns the number of pages.
+    pub fn shrink(&mut self) -> usize {
+        let _guard = LOCK.lock();
+        self.shrink_locked()
+    }
+
+    fn shrink_locked(&mut self) -> usize {
+        let mut pages = 0;
+        unsafe {
+            while !self.empty.is_null() {
+                let slab = self.empty;
+                unlink(&mut self.empty, slab);
+                frame::free_frames(memory::virt_to_phys(slab as usize),
+                                   self.order);
+                memory_stats::account_freed(Subsystem::Slab, self.slab_size());
+                self.slabs_count -= 1;
+                pages += 1 << self.order;
+            }
+        }
+        pages
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats{
+            object_size: self.object_size,
+            objects_per_slab: self.objects_per_slab,
+            objects_in_use: self.objects_in_use,
+            pages: self.slabs_count << self.order
+        }
+    }
+}
+
+// Called by the heap when it runs out of memory, nothing is released if
+// caches are locked at the moment (e.g. by a constructor allocating from
+// the heap).
+pub fn shrink_all() -> usize {
+    let mut pages = 0;
+    if let Some(_guard) = LOCK.try_lock() {
+        unsafe {
+            let mut cache = CACHES;
+            while !cache.is_null() {
+                pages += (*cache).shrink_locked();
+                cache = (*cache).next;
+            }
+        }
+    }
+    pages
+}
+
+pub fn dump() {
+    let _guard = LOCK.lock();
+    unsafe {
+        let mut cache = CACHES;
+        while !cache.is_null() {
+            let stats = (*cache).stats();
+            klog_info!("slab cache {}: {} objects of {} bytes in use, \
+                {} pages", (*cache).name, stats.objects_in_use,
+                stats.object_size, stats.pages);
+            cache = (*cache).next;
+        }
+    }
+}
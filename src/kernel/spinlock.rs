@@ -0,0 +1,45 @@
+use arch::cpu;
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+// Lock which also keeps interrupts disabled on the local CPU while held,
+// so it can be taken from interrupt handlers as well.
+pub struct SpinLock {
+    locked: AtomicBool
+}
+
+pub const SPIN_LOCK_INIT: SpinLock = SpinLock{locked: ATOMIC_BOOL_INIT};
+
+pub struct Guard<'a> {
+    lock: &'a SpinLock,
+    interrupts: bool
+}
+
+impl SpinLock {
+    pub fn lock(&self) -> Guard {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            while self.locked.load(Ordering::Relaxed) {
+                cpu::relax();
+            }
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<Guard> {
+        let interrupts = cpu::disable_interrupts();
+        if self.locked.compare_and_swap(false, true, Ordering::Acquire) {
+            cpu::restore_interrupts(interrupts);
+            None
+        } else {
+            Some(Guard{lock: self, interrupts: interrupts})
+        }
+    }
+}
+
+impl<'a> Drop for Guard<'a> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        cpu::restore_interrupts(self.interrupts);
+    }
+}
@@ -0,0 +1,133 @@
+use alloc::boxed::Box;
+use config::FRAME_ORDER_MAX;
+use frame::{self, FRAME_SIZE};
+use memory;
+use memory_stats::{self, Subsystem};
+use paging;
+
+// Kernel stack with an unmapped guard page below it, so overflowing it
+// faults instead of corrupting the adjacent memory.
+pub struct Stack {
+    name: &'static str,
+    next: *mut Stack,
+    guard: usize,
+    top: usize,
+    order: Option<usize> // none for stacks not allocated here
+}
+
+static mut STACKS: *mut Stack = 0 as *mut Stack;
+
+unsafe fn add(name: &'static str, guard: usize, top: usize,
+              order: Option<usize>) -> &'static mut Stack {
+    paging::unmap_small(guard);
+
+    let stack = Box::into_raw(Box::new(Stack{
+        name: name,
+        next: STACKS,
+        guard: guard,
+        top: top,
+        order: order
+    }));
+    STACKS = stack;
+    &mut *stack
+}
+
+// Allocates a stack of at least the given size for the named task.
+pub fn allocate(name: &'static str, size: usize) -> Option<&'static Stack> {
+    let mut order = 0;
+    while FRAME_SIZE << order < size + FRAME_SIZE {
+        order += 1;
+        if order > FRAME_ORDER_MAX {
+            return None;
+        }
+    }
+
+    frame::alloc_frames(order).map(|frames| {
+        let address = memory::phys_to_virt(frames);
+        let bytes = FRAME_SIZE << order;
+        memory_stats::account_allocated(Subsystem::Stacks, bytes);
+        unsafe { &*add(name, address, address + bytes, Some(order)) }
+    })
+}
+
+// Registers a stack placed elsewhere (e.g. the boot one) to guard it.
+pub unsafe fn register(name: &'static str, guard: usize,
+                       top: usize) -> &'static Stack {
+    &*add(name, guard, top, None)
+}
+
+// The stack must not be in use and no references to it may be left.
+pub unsafe fn free(stack: &'static Stack) {
+    let order = stack.order.expect("freeing registered stack");
+    let mut link = &mut STACKS;
+    while *link != stack as *const Stack as *mut Stack {
+        assert!(!(*link).is_null(), "freeing unknown stack");
+        link = &mut (**link).next;
+    }
+    *link = stack.next;
+
+    let bytes = FRAME_SIZE << order;
+    let frames = memory::virt_to_phys(stack.guard);
+    paging::map_direct(frames, FRAME_SIZE, paging::RAM);
+    frame::free_frames(frames, order);
+    memory_stats::account_freed(Subsystem::Stacks, bytes);
+    drop(Box::from_raw(stack as *const Stack as *mut Stack));
+}
+
+impl Stack {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    // Initial stack pointer.
+    pub fn top(&self) -> usize {
+        self.top
+    }
+
+    pub fn bottom(&self) -> usize {
+        self.guard + FRAME_SIZE
+    }
+
+    pub fn size(&self) -> usize {
+        self.top - self.bottom()
+    }
+
+    pub fn contains(&self, address: usize) -> bool {
+        address >= self.bottom() && address < self.top
+    }
+}
+
+// Returns the stack containing the given address.
+pub fn find(address: usize) -> Option<&'static Stack> {
+    unsafe {
+        let mut stack = STACKS;
+        while !stack.is_null() {
+            if (*stack).contains(address) {
+                return Some(&*stack);
+            }
+            stack = (*stack).next;
+        }
+        None
+    }
+}
+
+// Returns the stack whose guard page contains the given address.
+pub fn find_overflowed(address: usize) -> Option<&'static Stack> {
+    unsafe {
+        let mut stack = STACKS;
+        while !stack.is_null() {
+            if address >= (*stack).guard && address < (*stack).bottom() {
+                return Some(&*stack);
+            }
+            stack = (*stack).next;
+        }
+        None
+    }
+}
+
+// Called for every page fault, panics if it is caused by a stack overflow.
+pub fn check_overflow(address: usize) {
+    if let Some(stack) = find_overflowed(address) {
+        panic!("stack overflow on task {}", stack.name);
+    }
+}
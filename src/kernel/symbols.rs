@@ -0,0 +1,107 @@
+use config::SYMBOL_NAME_MAX;
+use core::fmt;
+use core::slice::from_raw_parts;
+use core::str::from_utf8_unchecked;
+
+// Every group of names starts with a full one, the rest of them share
+// a prefix with the previous name (see tools/symbols.py).
+const GROUP_SIZE: usize = 16;
+
+extern {
+    static __ksymbols: u32;
+    static __text_begin: u8;
+    static __text_end: u8;
+}
+
+// Table linked into the kernel image by the second link pass.
+struct Table {
+    // Sorted offsets of code symbols from the beginning of code.
+    offsets: &'static [u32],
+    // Offsets of the group names.
+    groups: &'static [u32],
+    // Pairs of the shared prefix and the suffix sizes followed by suffixes.
+    names: *const u8
+}
+
+fn table() -> Table {
+    unsafe {
+        let count = __ksymbols as usize;
+        let offsets = (&__ksymbols as *const u32).offset(1);
+        let groups = offsets.offset(count as isize);
+        let group_count = blocks_used!(count, GROUP_SIZE);
+        Table{
+            offsets: from_raw_parts(offsets, count),
+            groups: from_raw_parts(groups, group_count),
+            names: groups.offset(group_count as isize) as *const u8
+        }
+    }
+}
+
+pub struct Symbol {
+    name: [u8; SYMBOL_NAME_MAX],
+    len: usize,
+    // Offset of the address from the symbol start.
+    pub offset: usize
+}
+
+impl Symbol {
+    pub fn name(&self) -> &str {
+        unsafe { from_utf8_unchecked(&self.name[..self.len]) }
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+0x{:X}", self.name(), self.offset)
+    }
+}
+
+// Decodes the name of the symbol with the given index.
+unsafe fn decode_name(table: &Table, index: usize, symbol: &mut Symbol) {
+    let mut name = table.names.offset(table.groups[index / GROUP_SIZE] as
+                                      isize);
+    for _ in 0..index % GROUP_SIZE + 1 {
+        let (shared, size) = (*name as usize, *name.offset(1) as usize);
+        for i in 0..size {
+            symbol.name[shared + i] = *name.offset(2 + i as isize);
+        }
+        symbol.len = shared + size;
+        name = name.offset(2 + size as isize);
+    }
+}
+
+// Returns the code symbol containing the address, for return addresses
+// pass the address minus one as the call can be the last instruction.
+pub fn symbolize(address: usize) -> Option<Symbol> {
+    let (begin, end) = unsafe {
+        (&__text_begin as *const u8 as usize,
+         &__text_end as *const u8 as usize)
+    };
+    if address < begin || address >= end {
+        return None;
+    }
+
+    let table = table();
+    let offset = address - begin;
+    let (mut low, mut high) = (0, table.offsets.len());
+    while low < high {
+        let middle = (low + high) / 2;
+        if table.offsets[middle] as usize <= offset {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    if low == 0 {
+        return None;
+    }
+
+    let index = low - 1;
+    let mut symbol = Symbol{
+        name: [0; SYMBOL_NAME_MAX],
+        len: 0,
+        offset: offset - table.offsets[index] as usize
+    };
+    unsafe { decode_name(&table, index, &mut symbol); }
+    Some(symbol)
+}
@@ -0,0 +1,216 @@
+use arch::cpu;
+use config::{CPUS_MAX, TRACE_BUFFER_ORDER};
+use core::fmt::{self, Write};
+use core::mem::size_of;
+use core::slice::from_raw_parts;
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use frame::{self, FRAME_SIZE};
+use memory;
+use memory_stats::{self, Subsystem};
+use numa;
+
+// Static tracepoint placed by the trace! macro into the .tracepoints
+// section, its index there identifies events. The fields are public for
+// the macro only.
+pub struct Tracepoint {
+    pub name: &'static str,
+    pub module: &'static str,
+    // Source text of the recorded arguments, empty if one is omitted.
+    pub args: [&'static str; 2],
+    pub enabled: AtomicBool
+}
+
+pub const DISABLED: AtomicBool = ATOMIC_BOOL_INIT;
+
+// Binary event as it is kept in a buffer.
+#[derive(Clone, Copy)]
+struct Event {
+    ticks: u64,
+    point: u64,
+    args: [u64; 2]
+}
+
+// Ring of events of a single CPU, only this CPU writes to it.
+#[derive(Clone, Copy)]
+struct Buffer {
+    events: *mut Event,
+    capacity: usize,
+    written: usize
+}
+
+const BUFFER_INIT: Buffer =
+    Buffer{events: 0 as *mut Event, capacity: 0, written: 0};
+
+static mut BUFFERS: [Buffer; CPUS_MAX] = [BUFFER_INIT; CPUS_MAX];
+static mut OUTPUT: Option<fn(&str)> = None;
+
+// Set while dumping to keep the buffers intact.
+static PAUSED: AtomicBool = ATOMIC_BOOL_INIT;
+
+extern {
+    static __tracepoints_begin: Tracepoint;
+    static __tracepoints_end: Tracepoint;
+}
+
+fn tracepoints() -> &'static [Tracepoint] {
+    unsafe {
+        let begin = &__tracepoints_begin as *const Tracepoint;
+        let end = &__tracepoints_end as *const Tracepoint;
+        let count = (end as usize - begin as usize) / size_of::<Tracepoint>();
+        from_raw_parts(begin, count)
+    }
+}
+
+fn tracepoint_id(point: &'static Tracepoint) -> usize {
+    let begin = unsafe { &__tracepoints_begin as *const Tracepoint };
+    (point as *const Tracepoint as usize - begin as usize) /
+        size_of::<Tracepoint>()
+}
+
+// Sets the function to dump buffers with and allocates a buffer for the
+// boot CPU.
+pub fn init(write: fn(&str)) {
+    unsafe { OUTPUT = Some(write); }
+    init_cpu();
+}
+
+// Allocates a buffer for the calling CPU from its NUMA node, events of
+// CPUs without buffers are dropped.
+pub fn init_cpu() {
+    let cpu = cpu::id();
+    if cpu >= CPUS_MAX || unsafe { BUFFERS[cpu].capacity != 0 } {
+        return;
+    }
+    let bytes = FRAME_SIZE << TRACE_BUFFER_ORDER;
+    match frame::alloc_frames_on(TRACE_BUFFER_ORDER, numa::current_node()) {
+        Some(address) => {
+            memory_stats::account_allocated(Subsystem::Trace, bytes);
+            unsafe {
+                BUFFERS[cpu] = Buffer{
+                    events: memory::phys_to_virt(address) as *mut Event,
+                    capacity: bytes / size_of::<Event>(),
+                    written: 0
+                };
+            }
+        },
+        None => klog_warning!("trace: no memory for buffer of cpu {}", cpu)
+    }
+}
+
+// Called by enabled tracepoints, can be called from interrupt handlers.
+pub fn record(point: &'static Tracepoint, arg0: u64, arg1: u64) {
+    let cpu = cpu::id();
+    if cpu >= CPUS_MAX || PAUSED.load(Ordering::Relaxed) {
+        return;
+    }
+    let interrupts = cpu::disable_interrupts();
+    unsafe {
+        let buffer = &mut BUFFERS[cpu];
+        if buffer.capacity != 0 {
+            let index = buffer.written % buffer.capacity;
+            *buffer.events.offset(index as isize) = Event{
+                ticks: cpu::timestamp(),
+                point: tracepoint_id(point) as u64,
+                args: [arg0, arg1]
+            };
+            buffer.written += 1;
+        }
+    }
+    cpu::restore_interrupts(interrupts);
+}
+
+// Pattern is either a tracepoint name or a name prefix followed by '*'.
+fn matches(pattern: &str, name: &str) -> bool {
+    if pattern.ends_with("*") {
+        name.starts_with(&pattern[..pattern.len() - 1])
+    } else {
+        name.as_bytes() == pattern.as_bytes()
+    }
+}
+
+// Switches tracepoints matching the pattern, returns their number.
+pub fn set_enabled(pattern: &str, enabled: bool) -> usize {
+    let mut count = 0;
+    for point in tracepoints() {
+        if matches(pattern, point.name) {
+            point.enabled.store(enabled, Ordering::Relaxed);
+            count += 1;
+        }
+    }
+    count
+}
+
+// Enables tracepoints listed on the command line, e.g.
+// "trace=frame_*,stack_overflow". Can be called before init, events are
+// dropped until there is a buffer.
+pub fn configure(command_line: &str) {
+    for param in command_line.split(' ') {
+        if !param.starts_with("trace=") {
+            continue;
+        }
+        for pattern in param["trace=".len()..].split(',') {
+            if set_enabled(pattern, true) == 0 {
+                klog_warning!("trace: no tracepoint matches {}", pattern);
+            }
+        }
+    }
+}
+
+pub fn is_enabled() -> bool {
+    tracepoints().iter().any(|point| point.enabled.load(Ordering::Relaxed))
+}
+
+struct Output(fn(&str));
+
+impl fmt::Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        (self.0)(s);
+        Ok(())
+    }
+}
+
+// Writes the tracepoint table and the buffered events as text lines to
+// be decoded by tools/trace-decode.py. Tracing is paused meanwhile, so
+// a nested call (e.g. by a panic while dumping) does nothing.
+pub fn dump() {
+    let mut out = match unsafe { OUTPUT } {
+        Some(write) => Output(write),
+        None => return
+    };
+    if PAUSED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let _ = write!(out, "trace: begin {}\n", cpu::timestamp_frequency());
+    for (id, point) in tracepoints().iter().enumerate() {
+        let _ = write!(out, "trace: point {} {} {}\n", id, point.module,
+                       point.name);
+        for (index, arg) in point.args.iter().enumerate() {
+            if !arg.is_empty() {
+                let _ = write!(out, "trace: arg {} {} {}\n", id, index, arg);
+            }
+        }
+    }
+    for cpu in 0..CPUS_MAX {
+        let buffer = unsafe { BUFFERS[cpu] };
+        if buffer.capacity == 0 {
+            continue;
+        }
+        let first = if buffer.written > buffer.capacity {
+            buffer.written - buffer.capacity
+        } else {
+            0
+        };
+        let _ = write!(out, "trace: cpu {} {} lost\n", cpu, first);
+        for seq in first..buffer.written {
+            let index = (seq % buffer.capacity) as isize;
+            let event = unsafe { *buffer.events.offset(index) };
+            let _ = write!(out, "trace: event {} {:x} {} {:x} {:x}\n", cpu,
+                           event.ticks, event.point, event.args[0],
+                           event.args[1]);
+        }
+    }
+    let _ = write!(out, "trace: end\n");
+
+    PAUSED.store(false, Ordering::SeqCst);
+}
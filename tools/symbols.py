@@ -0,0 +1,123 @@
+#!/usr/bin/env python
+
+# Generates assembly of the kernel symbol table (see src/kernel/symbols.rs)
+# from 'nm -n' output of the kernel linked without it. Only code symbols
+# are included as the table is placed after the code, so their addresses
+# are the same once it is linked in.
+
+import argparse
+import re
+import sys
+
+# Names of each group start with a full one, the rest share a prefix
+# with the previous name.
+GROUP_SIZE = 16
+NAME_MAX = 255
+
+BEGIN_SYMBOL = '__text_begin'
+END_SYMBOL = '__text_end'
+
+parser = argparse.ArgumentParser(description='Arwen OS symbol table maker.')
+parser.add_argument('nm', nargs='?', type=argparse.FileType('r'),
+                    default=sys.stdin,
+                    help='output of \'nm -n\' for the kernel (default: stdin)')
+parser.add_argument('--empty', dest='empty', action='store_true',
+                    help='make an empty table for the first link pass')
+args = parser.parse_args()
+
+ESCAPES = {
+    'SP': '@', 'BP': '*', 'RF': '&', 'LT': '<', 'GT': '>', 'LP': '(',
+    'RP': ')', 'C': ',', 'u20': ' ', 'u27': '\'', 'u5b': '[', 'u5d': ']',
+    'u7b': '{', 'u7d': '}', 'u7e': '~'
+}
+
+def demangle_ident(ident):
+    if ident.startswith('_$'):
+        ident = ident[1:]
+    ident = re.sub(r'\$([A-Za-z0-9]+)\$',
+                   lambda m: ESCAPES.get(m.group(1), m.group(0)), ident)
+    return ident.replace('..', '::')
+
+# Turns '_ZN6kernel5frame11free_frames17h0123456789abcdefE' into
+# 'kernel::frame::free_frames', other names are kept as they are.
+def demangle(name):
+    if not name.startswith('_ZN') or not name.endswith('E'):
+        return name
+    idents = []
+    pos = 3
+    while pos < len(name) - 1:
+        match = re.match(r'\d+', name[pos:])
+        if not match:
+            return name
+        pos += len(match.group(0))
+        size = int(match.group(0))
+        idents.append(name[pos:pos + size])
+        pos += size
+    if idents and re.match(r'^h[0-9a-f]{16}$', idents[-1]):
+        idents.pop()
+    return '::'.join(map(demangle_ident, idents))
+
+def read_symbols(lines):
+    symbols = {}
+    begin, end = None, None
+    for line in lines:
+        fields = line.split()
+        if len(fields) != 3:
+            continue
+        address, kind, name = int(fields[0], 16), fields[1], fields[2]
+        if name == BEGIN_SYMBOL:
+            begin = address
+        elif name == END_SYMBOL:
+            end = address
+        elif kind in 'tTwW':
+            # aliases of the same address are represented by one name
+            symbols.setdefault(address, name)
+    if begin is None or end is None:
+        sys.stderr.write('error: no %s or %s symbol found\n'% \
+            (BEGIN_SYMBOL, END_SYMBOL))
+        sys.exit(1)
+    return [(address - begin,
+             bytearray(demangle(name).encode('utf-8'))[:NAME_MAX])
+            for address, name in sorted(symbols.items())
+            if begin <= address < end]
+
+def shared_prefix(a, b):
+    size = 0
+    while size < min(len(a), len(b)) and a[size] == b[size]:
+        size += 1
+    return size
+
+def encode_names(symbols):
+    names, groups = [], []
+    previous = bytearray()
+    for index, (_, name) in enumerate(symbols):
+        if index % GROUP_SIZE == 0:
+            groups.append(len(names))
+            previous = bytearray()
+        shared = shared_prefix(previous, name)
+        suffix = name[shared:]
+        names += [shared, len(suffix)] + list(suffix)
+        previous = name
+    return names, groups
+
+def write_longs(out, values):
+    for pos in range(0, len(values), 8):
+        out.write('\t.long %s\n'%', '.join(map(str, values[pos:pos + 8])))
+
+def write_bytes(out, values):
+    for pos in range(0, len(values), 16):
+        out.write('\t.byte %s\n'%', '.join(map(str, values[pos:pos + 16])))
+
+symbols = [] if args.empty else read_symbols(args.nm)
+names, groups = encode_names(symbols)
+
+out = sys.stdout
+out.write('/* generated by tools/symbols.py, do not modify */\n')
+out.write('\t.global __ksymbols\n')
+out.write('\t.section .ksymbols, "a"\n')
+out.write('\t.balign 8\n')
+out.write('__ksymbols:\n')
+out.write('\t.long %d\n'%len(symbols))
+write_longs(out, [offset for offset, _ in symbols])
+write_longs(out, groups)
+write_bytes(out, names)
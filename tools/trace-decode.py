@@ -0,0 +1,95 @@
+#!/usr/bin/env python
+
+# Decodes trace buffers dumped by the kernel (see src/kernel/trace.rs) from
+# a serial log into a timeline of events of all CPUs ordered by time.
+
+import argparse
+import sys
+
+PREFIX = 'trace: '
+
+parser = argparse.ArgumentParser(description='Arwen OS trace decoder.')
+parser.add_argument('log', nargs='?', type=argparse.FileType('r'),
+                    default=sys.stdin,
+                    help='serial log containing a trace dump (default: stdin)')
+parser.add_argument('--raw', dest='raw', action='store_true',
+                    help='print timestamps in ticks instead of seconds')
+args = parser.parse_args()
+
+class Dump:
+    def __init__(self, frequency):
+        self.frequency = frequency
+        self.points = {}
+        self.events = []
+        self.lost = {}
+
+class Point:
+    def __init__(self, module, name):
+        self.module = module
+        self.name = name
+        self.args = [None, None]
+
+def parse(lines):
+    dumps = []
+    dump = None
+    for number, line in enumerate(lines, 1):
+        pos = line.find(PREFIX)
+        if pos < 0:
+            continue
+        fields = line[pos + len(PREFIX):].rstrip('\r\n').split(' ', 5)
+        try:
+            kind = fields[0]
+            if kind == 'begin':
+                dump = Dump(int(fields[1]))
+            elif dump is None:
+                continue
+            elif kind == 'point':
+                dump.points[int(fields[1])] = Point(fields[2], fields[3])
+            elif kind == 'arg':
+                text = ' '.join(fields[3:])
+                dump.points[int(fields[1])].args[int(fields[2])] = text
+            elif kind == 'cpu':
+                dump.lost[int(fields[1])] = int(fields[2])
+            elif kind == 'event':
+                dump.events.append((int(fields[2], 16), int(fields[1]),
+                                    int(fields[3]), int(fields[4], 16),
+                                    int(fields[5], 16)))
+            elif kind == 'end':
+                dumps.append(dump)
+                dump = None
+        except (IndexError, KeyError, ValueError):
+            sys.stderr.write('warning: bad trace line %d\n'%number)
+    if dump is not None:
+        sys.stderr.write('warning: trace dump is truncated\n')
+        dumps.append(dump)
+    return dumps
+
+def format_time(dump, ticks):
+    if args.raw or not dump.frequency:
+        return '%16d'%ticks
+    micros = ticks * 1000000 // dump.frequency
+    return '%5d.%06d'%(micros // 1000000, micros % 1000000)
+
+def print_dump(dump):
+    for cpu in sorted(dump.lost):
+        if dump.lost[cpu]:
+            print('cpu %d lost %d oldest events'%(cpu, dump.lost[cpu]))
+    for ticks, cpu, id, arg0, arg1 in sorted(dump.events):
+        point = dump.points.get(id)
+        if point is None:
+            text = 'unknown tracepoint %d 0x%x 0x%x'%(id, arg0, arg1)
+        else:
+            text = '%s %s'%(point.module, point.name)
+            for name, value in zip(point.args, (arg0, arg1)):
+                if name is not None:
+                    text += ' %s=0x%x'%(name, value)
+        print('[%s] %d %s'%(format_time(dump, ticks), cpu, text))
+
+dumps = parse(args.log)
+if not dumps:
+    sys.stderr.write('error: no trace dump found\n')
+    sys.exit(1)
+for index, dump in enumerate(dumps):
+    if index:
+        print('')
+    print_dump(dump)